@@ -0,0 +1,162 @@
+//! Totals of records grouped by some key, emitted once the walk is done.
+
+use crate::date::{Period, Time};
+use crate::{Record, ShortStat};
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Totals for a single author.
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct AuthorStat {
+    #[serde(rename(serialize = "an"))]
+    pub name: String,
+    #[serde(rename(serialize = "ae"))]
+    pub email: String,
+    #[serde(rename(serialize = "n"))]
+    pub commits: usize,
+    #[serde(flatten)]
+    pub short_stat: ShortStat,
+}
+
+/// Totals per author, where an author is a distinct name and email pair.
+#[derive(Clone, Debug, Default)]
+pub struct ByAuthor {
+    authors: HashMap<(String, String), AuthorStat>,
+}
+
+impl ByAuthor {
+    /// Count a record, which must carry its author.
+    pub fn add(&mut self, record: &Record) {
+        let name = record.author_name.clone().unwrap_or_default();
+        let email = record.author_email.clone().unwrap_or_default();
+        let author = self
+            .authors
+            .entry((name.clone(), email.clone()))
+            .or_insert_with(|| AuthorStat {
+                name,
+                email,
+                commits: 0,
+                short_stat: ShortStat::default(),
+            });
+        author.commits += 1;
+        author.short_stat += &record.short_stat;
+    }
+
+    /// Totals sorted by insertions, most first, then by author.
+    pub fn finish(self) -> Vec<AuthorStat> {
+        let mut authors: Vec<_> = self.authors.into_values().collect();
+        authors.sort_by(|a, b| {
+            b.short_stat
+                .insertions
+                .cmp(&a.short_stat.insertions)
+                .then_with(|| (&a.name, &a.email).cmp(&(&b.name, &b.email)))
+        });
+        authors
+    }
+}
+
+/// Totals for a single file extension.
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct ExtensionStat {
+    /// The extension without its dot, empty for files that have none.
+    pub extension: String,
+    #[serde(flatten)]
+    pub short_stat: ShortStat,
+}
+
+/// Totals per extension of each changed path, where `files_changed` counts
+/// every time a file with that extension changed.
+#[derive(Clone, Debug, Default)]
+pub struct ByExtension {
+    extensions: HashMap<String, ShortStat>,
+}
+
+impl ByExtension {
+    /// Count the files of a record, which must carry them.
+    pub fn add(&mut self, record: &Record) {
+        for file in &record.files {
+            let extension = Path::new(&file.path)
+                .extension()
+                .map(|extension| extension.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let short_stat = self.extensions.entry(extension).or_default();
+            short_stat.files_changed += 1;
+            short_stat.insertions += file.insertions;
+            short_stat.deletions += file.deletions;
+        }
+    }
+
+    /// Totals sorted by insertions, most first, then by extension.
+    pub fn finish(self) -> Vec<ExtensionStat> {
+        let mut extensions: Vec<_> = self
+            .extensions
+            .into_iter()
+            .map(|(extension, short_stat)| ExtensionStat {
+                extension,
+                short_stat,
+            })
+            .collect();
+        extensions.sort_by(|a, b| {
+            b.short_stat
+                .insertions
+                .cmp(&a.short_stat.insertions)
+                .then_with(|| a.extension.cmp(&b.extension))
+        });
+        extensions
+    }
+}
+
+/// Totals for a single period of time.
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct PeriodStat {
+    /// The period's first day, or month for months.
+    pub period: String,
+    #[serde(rename(serialize = "n"))]
+    pub commits: usize,
+    #[serde(flatten)]
+    pub short_stat: ShortStat,
+}
+
+/// Totals per period of author time, in the author's own timezone.
+#[derive(Clone, Debug)]
+pub struct ByPeriod {
+    period: Period,
+    periods: BTreeMap<String, (usize, ShortStat)>,
+}
+
+impl ByPeriod {
+    pub fn new(period: Period) -> Self {
+        Self {
+            period,
+            periods: BTreeMap::new(),
+        }
+    }
+
+    /// Count a record, which must carry its time as epoch seconds.
+    pub fn add(&mut self, record: &Record) {
+        let (seconds, offset_minutes) = match (&record.time, record.offset_minutes) {
+            (Some(Time::Epoch(seconds)), Some(offset_minutes)) => (*seconds, offset_minutes),
+            _ => return,
+        };
+        let (commits, short_stat) = self
+            .periods
+            .entry(self.period.key(seconds, offset_minutes))
+            .or_default();
+        *commits += 1;
+        *short_stat += &record.short_stat;
+    }
+
+    /// Totals in order of time, oldest first. Periods without commits are
+    /// left out.
+    pub fn finish(self) -> Vec<PeriodStat> {
+        self.periods
+            .into_iter()
+            .map(|(period, (commits, short_stat))| PeriodStat {
+                period,
+                commits,
+                short_stat,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,428 @@
+//! Minimal date handling for commit timestamps, which git stores as seconds
+//! since the epoch plus a UTC offset in minutes.
+
+use git2::Error;
+use serde_derive::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Parse a date into seconds since the epoch.
+///
+/// Accepts raw epoch seconds, `YYYY-MM-DD`, or an RFC 3339 style
+/// `YYYY-MM-DDTHH:MM:SS` optionally followed by `Z` or a `±HH:MM` offset.
+/// Dates without an offset are taken as UTC. If `end_of_day` is set, a bare
+/// `YYYY-MM-DD` means the last second of that day rather than the first.
+pub fn parse(s: &str, end_of_day: bool) -> Result<i64, Error> {
+    let invalid = || Error::from_str(&format!("invalid date: {}", s));
+    let number = |part: Option<&str>| part.and_then(|p| p.parse::<i64>().ok()).ok_or_else(invalid);
+    if let Ok(seconds) = s.parse::<i64>() {
+        return Ok(seconds);
+    }
+    let (date, time) = match s.find(&['T', ' '][..]) {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    let mut parts = date.splitn(3, '-');
+    let year = number(parts.next())?;
+    let month = number(parts.next())?;
+    let day = number(parts.next())?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+    let days = days_from_civil(year, month, day);
+    let time = match time {
+        Some(time) => time,
+        None if end_of_day => return Ok((days + 1) * SECONDS_PER_DAY - 1),
+        None => return Ok(days * SECONDS_PER_DAY),
+    };
+
+    let (clock, offset) = match time.find(&['Z', '+', '-'][..]) {
+        Some(i) => (&time[..i], &time[i..]),
+        None => (time, ""),
+    };
+    let mut parts = clock.splitn(3, ':');
+    let hour = number(parts.next())?;
+    let minute = number(parts.next())?;
+    let second = match parts.next() {
+        Some(p) => number(Some(p))?,
+        None => 0,
+    };
+    if hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid());
+    }
+    let offset_minutes = match offset {
+        "" | "Z" => 0,
+        _ => {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let offset = offset[1..].replace(':', "");
+            if offset.len() != 4 || !offset.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            let hours = number(Some(&offset[..2]))?;
+            let minutes = number(Some(&offset[2..]))?;
+            sign * (hours * 60 + minutes)
+        }
+    };
+    Ok(days * SECONDS_PER_DAY + hour * 3600 + minute * 60 + second - offset_minutes * 60)
+}
+
+/// Length of a month of the proleptic Gregorian calendar, from 1 to 12.
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// How times are written out.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DateFormat {
+    /// Seconds since the epoch, as a number.
+    #[default]
+    Epoch,
+    /// `2005-04-07T22:13:13+02:00`, in the time's own offset.
+    Rfc3339,
+    /// `2005-04-07 22:13:13 +0200`, as `git log --date=iso`.
+    Iso,
+}
+
+impl FromStr for DateFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "epoch" => Ok(DateFormat::Epoch),
+            "rfc3339" => Ok(DateFormat::Rfc3339),
+            "iso" => Ok(DateFormat::Iso),
+            _ => Err(Error::from_str(&format!("unknown date format: {}", s))),
+        }
+    }
+}
+
+/// A span of calendar time to group commits by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Period {
+    Day,
+    /// An ISO week, starting on Monday.
+    Week,
+    Month,
+}
+
+impl Period {
+    /// The period `seconds` since the epoch falls in, at a UTC offset of
+    /// `offset_minutes`. Days and weeks are written as their first day, such as
+    /// `2024-01-15`, and months as `2024-01`.
+    pub fn key(self, seconds: i64, offset_minutes: i32) -> String {
+        let local = seconds + i64::from(offset_minutes) * 60;
+        let days = local.div_euclid(SECONDS_PER_DAY);
+        // 1970-01-01 was a Thursday, three days after a Monday
+        let days = match self {
+            Period::Week => days - (days + 3).rem_euclid(7),
+            _ => days,
+        };
+        let (year, month, day) = civil_from_days(days);
+        match self {
+            Period::Day | Period::Week => format!("{:04}-{:02}-{:02}", year, month, day),
+            Period::Month => format!("{:04}-{:02}", year, month),
+        }
+    }
+}
+
+impl FromStr for Period {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(Period::Day),
+            "week" => Ok(Period::Week),
+            "month" => Ok(Period::Month),
+            _ => Err(Error::from_str(&format!("unknown period: {}", s))),
+        }
+    }
+}
+
+/// A time as written out, either seconds since the epoch or a formatted date.
+#[derive(Clone, Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Time {
+    Epoch(i64),
+    Formatted(String),
+}
+
+impl Time {
+    /// Write `seconds` since the epoch, at a UTC offset of `offset_minutes`.
+    pub fn new(seconds: i64, offset_minutes: i32, format: DateFormat) -> Self {
+        let local = seconds + i64::from(offset_minutes) * 60;
+        let (year, month, day) = civil_from_days(local.div_euclid(SECONDS_PER_DAY));
+        let clock = local.rem_euclid(SECONDS_PER_DAY);
+        let (hour, minute, second) = (clock / 3600, clock / 60 % 60, clock % 60);
+        let sign = if offset_minutes < 0 { '-' } else { '+' };
+        let (offset_hours, offset_minutes) = (offset_minutes.abs() / 60, offset_minutes.abs() % 60);
+        let date = format!("{:04}-{:02}-{:02}", year, month, day);
+        let clock = format!("{:02}:{:02}:{:02}", hour, minute, second);
+        match format {
+            DateFormat::Epoch => Time::Epoch(seconds),
+            DateFormat::Rfc3339 => Time::Formatted(format!(
+                "{}T{}{}{:02}:{:02}",
+                date, clock, sign, offset_hours, offset_minutes
+            )),
+            DateFormat::Iso => Time::Formatted(format!(
+                "{} {} {}{:02}{:02}",
+                date, clock, sign, offset_hours, offset_minutes
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Time::Epoch(seconds) => write!(f, "{}", seconds),
+            Time::Formatted(date) => f.write_str(date),
+        }
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+///
+/// See: http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The proleptic Gregorian date of a number of days since 1970-01-01.
+///
+/// See: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEAP_DAY_2024: i64 = 1_709_164_800;
+
+    #[test]
+    fn parses_the_epoch() {
+        assert_eq!(parse("0", false).unwrap(), 0);
+        assert_eq!(parse("1970-01-01", false).unwrap(), 0);
+        assert_eq!(parse("1970-01-01T00:00:00Z", false).unwrap(), 0);
+        assert_eq!(parse("1969-12-31", false).unwrap(), -SECONDS_PER_DAY);
+        assert_eq!(parse("1700000000", true).unwrap(), 1_700_000_000);
+    }
+
+    #[test]
+    fn parses_leap_days() {
+        assert_eq!(parse("2024-02-29", false).unwrap(), LEAP_DAY_2024);
+        assert_eq!(
+            parse("2024-03-01", false).unwrap(),
+            LEAP_DAY_2024 + SECONDS_PER_DAY
+        );
+        assert_eq!(parse("2000-02-29", false).unwrap(), 951_782_400);
+    }
+
+    #[test]
+    fn parses_offsets() {
+        let noon = LEAP_DAY_2024 + 12 * 3600;
+        assert_eq!(parse("2024-02-29T12:00:00", false).unwrap(), noon);
+        assert_eq!(parse("2024-02-29 12:00:00Z", false).unwrap(), noon);
+        assert_eq!(
+            parse("2024-02-29T12:00:00+02:00", false).unwrap(),
+            noon - 2 * 3600
+        );
+        assert_eq!(
+            parse("2024-02-29T12:00:00-05:30", false).unwrap(),
+            noon + 330 * 60
+        );
+        assert_eq!(
+            parse("2024-02-29T12:00:00+0200", false).unwrap(),
+            noon - 2 * 3600
+        );
+        assert_eq!(parse("2024-02-29T12:00", false).unwrap(), noon);
+    }
+
+    #[test]
+    fn bare_until_dates_end_with_their_day() {
+        assert_eq!(
+            parse("2024-02-29", true).unwrap(),
+            LEAP_DAY_2024 + SECONDS_PER_DAY - 1
+        );
+        // Ignored given a time
+        assert_eq!(parse("2024-02-29T00:00:00", true).unwrap(), LEAP_DAY_2024);
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        for date in &[
+            "2023-02-29",
+            "1900-02-29",
+            "2023-04-31",
+            "2023-13-01",
+            "2023-00-10",
+            "2023-01-00",
+            "2023-01",
+            "yesterday",
+            "2023-01-01T24:00:00",
+            "2023-01-01T12:60:00",
+            "2023-01-01T12:00:00+2",
+        ] {
+            assert!(parse(date, false).is_err(), "{}", date);
+        }
+    }
+
+    #[test]
+    fn rejects_offsets_that_are_not_four_digits() {
+        for date in &[
+            "2024-01-01T00:00+1\u{e9}1",
+            "2024-01-01T00:00+\u{e9}\u{e9}",
+            "2024-01-01T00:00-+100",
+            "2024-01-01T00:00+1 00",
+            "2024-01-01T00:00+12345",
+        ] {
+            assert!(parse(date, false).is_err(), "{}", date);
+        }
+    }
+
+    #[test]
+    fn months_have_their_lengths() {
+        let lengths: Vec<_> = (1..=12).map(|month| days_in_month(2023, month)).collect();
+        assert_eq!(lengths, [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31]);
+        let days = |year| days_from_civil(year + 1, 1, 1) - days_from_civil(year, 1, 1);
+        for year in 1890..2110 {
+            let feb = days_in_month(year, 2);
+            assert_eq!(days(year), 337 + feb, "{}", year);
+        }
+    }
+
+    #[test]
+    fn civil_dates_round_trip() {
+        for days in -800_000..800_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert!(day >= 1 && day <= days_in_month(year, month), "{}", days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(
+            civil_from_days(LEAP_DAY_2024 / SECONDS_PER_DAY),
+            (2024, 2, 29)
+        );
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn formats_times_in_their_own_offset() {
+        let format = |seconds, offset, format| Time::new(seconds, offset, format).to_string();
+        let noon = LEAP_DAY_2024 + 12 * 3600;
+        assert_eq!(format(noon, 0, DateFormat::Epoch), noon.to_string());
+        assert_eq!(
+            format(noon, 0, DateFormat::Rfc3339),
+            "2024-02-29T12:00:00+00:00"
+        );
+        assert_eq!(
+            format(noon, 120, DateFormat::Rfc3339),
+            "2024-02-29T14:00:00+02:00"
+        );
+        assert_eq!(
+            format(noon, -330, DateFormat::Rfc3339),
+            "2024-02-29T06:30:00-05:30"
+        );
+        assert_eq!(
+            format(noon, -45, DateFormat::Iso),
+            "2024-02-29 11:15:00 -0045"
+        );
+        // Offsets can carry times over into another day
+        assert_eq!(
+            format(LEAP_DAY_2024, -60, DateFormat::Iso),
+            "2024-02-28 23:00:00 -0100"
+        );
+        assert_eq!(
+            format(noon + 12 * 3600 - 1, 840, DateFormat::Iso),
+            "2024-03-01 13:59:59 +1400"
+        );
+        assert_eq!(
+            format(-1, 0, DateFormat::Rfc3339),
+            "1969-12-31T23:59:59+00:00"
+        );
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        let week = |seconds| Period::Week.key(seconds, 0);
+        // Thursday, Sunday and Monday
+        assert_eq!(week(0), "1969-12-29");
+        assert_eq!(week(LEAP_DAY_2024), "2024-02-26");
+        assert_eq!(week(1_709_510_399), "2024-02-26");
+        assert_eq!(week(1_709_510_400), "2024-03-04");
+    }
+
+    #[test]
+    fn weeks_span_the_new_year() {
+        let week = |seconds| Period::Week.key(seconds, 0);
+        // 2021-01-03, a Sunday, is in the week of 2020-12-28
+        assert_eq!(week(1_609_632_000), "2020-12-28");
+        assert_eq!(week(1_609_718_400), "2021-01-04");
+        // 2025-01-01, a Wednesday, is in the week of 2024-12-30
+        assert_eq!(week(1_735_689_600), "2024-12-30");
+        assert_eq!(Period::Month.key(1_609_632_000, 0), "2021-01");
+    }
+
+    #[test]
+    fn months_and_days() {
+        assert_eq!(Period::Month.key(LEAP_DAY_2024, 0), "2024-02");
+        assert_eq!(
+            Period::Month.key(LEAP_DAY_2024 + SECONDS_PER_DAY, 0),
+            "2024-03"
+        );
+        assert_eq!(Period::Day.key(LEAP_DAY_2024, 0), "2024-02-29");
+        assert_eq!(Period::Day.key(-1, 0), "1969-12-31");
+    }
+
+    #[test]
+    fn periods_are_in_the_authors_timezone() {
+        // 23:30 UTC on 2024-02-29 is already 2024-03-01 an hour east
+        let late = LEAP_DAY_2024 + SECONDS_PER_DAY - 1800;
+        assert_eq!(Period::Day.key(late, 0), "2024-02-29");
+        assert_eq!(Period::Day.key(late, 60), "2024-03-01");
+        assert_eq!(Period::Month.key(late, 60), "2024-03");
+        // and 00:30 UTC still 2024-02-28 five and a half hours west
+        let early = LEAP_DAY_2024 + 1800;
+        assert_eq!(Period::Day.key(early, 0), "2024-02-29");
+        assert_eq!(Period::Day.key(early, -330), "2024-02-28");
+        // Moving back over midnight on a Monday moves back a week
+        assert_eq!(Period::Week.key(1_609_718_400 + 1800, -60), "2020-12-28");
+        assert_eq!(Period::Week.key(1_609_718_400 + 1800, 0), "2021-01-04");
+    }
+
+    #[test]
+    fn parses_periods() {
+        assert_eq!("day".parse::<Period>().unwrap(), Period::Day);
+        assert_eq!("week".parse::<Period>().unwrap(), Period::Week);
+        assert_eq!("month".parse::<Period>().unwrap(), Period::Month);
+        assert!("year".parse::<Period>().is_err());
+    }
+}
@@ -0,0 +1,137 @@
+//! Decoding commit messages and signatures written in the encoding their
+//! commit declares, which git records in an `encoding` header when it isn't
+//! UTF-8.
+//!
+//! Only UTF-8 and the common single byte Western encodings are known, which
+//! cover the legacy encodings git users met in practice, without a full
+//! encoding library. Text in any other encoding is decoded as UTF-8.
+
+use git2::Error;
+use std::str::FromStr;
+
+/// A character encoding commit text can be decoded from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    /// Invalid sequences are replaced with U+FFFD.
+    Utf8,
+    /// ISO-8859-1, mapping each byte to the code point of the same value.
+    Latin1,
+    /// Windows-1252, Latin-1 with printable characters in place of most of
+    /// the C1 controls.
+    Windows1252,
+}
+
+/// Characters of Windows-1252 bytes 0x80 to 0x9F. The five bytes it leaves
+/// undefined decode as their C1 controls, as web browsers do.
+const WINDOWS_1252_C1: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}',
+];
+
+impl Encoding {
+    /// The encoding a label such as `ISO-8859-1` names, ignoring case.
+    pub fn for_label(label: &str) -> Option<Self> {
+        match label.trim().to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "latin1" | "latin-1" | "l1" => {
+                Some(Encoding::Latin1)
+            }
+            "windows-1252" | "cp1252" | "x-cp1252" => Some(Encoding::Windows1252),
+            _ => None,
+        }
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Latin1 => bytes.iter().map(|&b| char::from(b)).collect(),
+            Encoding::Windows1252 => bytes
+                .iter()
+                .map(|&b| match b {
+                    0x80..=0x9f => WINDOWS_1252_C1[usize::from(b - 0x80)],
+                    _ => char::from(b),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::for_label(s).ok_or_else(|| {
+            Error::from_str(&format!(
+                "unknown encoding: {}, expected utf-8, iso-8859-1 or windows-1252",
+                s
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_the_windows_1252_c1_block() {
+        let bytes: Vec<u8> = (0x80..=0x9f).collect();
+        let decoded: Vec<char> = Encoding::Windows1252.decode(&bytes).chars().collect();
+        assert_eq!(decoded, WINDOWS_1252_C1);
+        assert_eq!(Encoding::Windows1252.decode(b"\x80"), "\u{20ac}");
+        assert_eq!(
+            Encoding::Windows1252.decode(b"\x93hi\x94"),
+            "\u{201c}hi\u{201d}"
+        );
+        assert_eq!(Encoding::Windows1252.decode(b"\x9f"), "\u{178}");
+    }
+
+    #[test]
+    fn decodes_undefined_windows_1252_bytes_as_controls() {
+        for &b in &[0x81, 0x8d, 0x8f, 0x90, 0x9d] {
+            assert_eq!(
+                Encoding::Windows1252.decode(&[b]),
+                char::from(b).to_string(),
+                "{:#x}",
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_latin1_byte_for_byte() {
+        let bytes: Vec<u8> = (0..=0xff).collect();
+        let decoded: Vec<u32> = Encoding::Latin1
+            .decode(&bytes)
+            .chars()
+            .map(u32::from)
+            .collect();
+        assert_eq!(decoded, (0..=0xff).collect::<Vec<_>>());
+        // Outside the C1 block, Windows-1252 is the same
+        assert_eq!(Encoding::Windows1252.decode(b"caf\xe9"), "caf\u{e9}");
+    }
+
+    #[test]
+    fn replaces_invalid_utf8() {
+        assert_eq!(Encoding::Utf8.decode("café".as_bytes()), "café");
+        assert_eq!(Encoding::Utf8.decode(b"caf\xe9"), "caf\u{fffd}");
+    }
+
+    #[test]
+    fn knows_labels_ignoring_case() {
+        assert_eq!(Encoding::for_label("UTF-8"), Some(Encoding::Utf8));
+        assert_eq!(Encoding::for_label(" ISO-8859-1 "), Some(Encoding::Latin1));
+        assert_eq!(Encoding::for_label("CP1252"), Some(Encoding::Windows1252));
+        assert_eq!(Encoding::for_label("KOI8-R"), None);
+        assert_eq!(Encoding::for_label(""), None);
+    }
+
+    #[test]
+    fn parses_only_known_encodings() {
+        assert_eq!("latin1".parse::<Encoding>().unwrap(), Encoding::Latin1);
+        let e = "shift_jis".parse::<Encoding>().unwrap_err();
+        assert!(e.message().contains("unknown encoding: shift_jis"));
+    }
+}
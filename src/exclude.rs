@@ -0,0 +1,81 @@
+//! Pathspecs excluding paths, written as `:!path`, `:^path` or
+//! `:(exclude)path` as in git. libgit2 has no pathspec magic, so these are
+//! split out and applied to whatever the other pathspecs match.
+
+use crate::pathspec::Globs;
+use crate::{pathspec_flags, WalkOptions};
+use git2::{Error, Pathspec, PathspecFlags};
+use std::path::Path;
+
+/// The path a pathspec excludes, if it's an exclusion. With `literal`,
+/// nothing is magic, so `:!x` is just a path.
+pub fn exclusion(spec: &str, literal: bool) -> Option<&str> {
+    if literal {
+        return None;
+    }
+    [":(exclude)", ":!", ":^"]
+        .iter()
+        .find_map(|magic| spec.strip_prefix(magic))
+}
+
+/// How excluded paths are matched, as the other pathspecs are.
+enum Matcher {
+    Pathspec(Pathspec, PathspecFlags),
+    Globs(Globs),
+}
+
+/// Matches paths left out by exclusion pathspecs.
+pub struct Excludes {
+    matcher: Option<Matcher>,
+}
+
+impl Excludes {
+    /// Collect the exclusions among the walk's pathspecs.
+    pub fn new(opts: &WalkOptions) -> Result<Self, Error> {
+        let excluded: Vec<_> = opts
+            .specs
+            .iter()
+            .filter_map(|spec| exclusion(spec, opts.literal_pathspecs))
+            .map(String::from)
+            .collect();
+        let matcher = if excluded.is_empty() {
+            None
+        } else if opts.pathspec_glob && !opts.follow {
+            Some(Matcher::Globs(Globs::new(&excluded, opts.pathspec_icase)?))
+        } else {
+            Some(Matcher::Pathspec(
+                Pathspec::new(excluded)?,
+                pathspec_flags(opts),
+            ))
+        };
+        Ok(Self { matcher })
+    }
+
+    /// Match paths by `globs`, which aren't written as exclusions.
+    pub fn globs(globs: &[String]) -> Result<Self, Error> {
+        let matcher = if globs.is_empty() {
+            None
+        } else {
+            Some(Matcher::Pathspec(
+                Pathspec::new(globs)?,
+                PathspecFlags::DEFAULT,
+            ))
+        };
+        Ok(Self { matcher })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matcher.is_none()
+    }
+
+    /// Whether `path`, from the top of the working tree, is left out.
+    pub fn excludes(&self, path: &str) -> bool {
+        match self.matcher {
+            Some(Matcher::Pathspec(ref pathspec, flags)) => {
+                pathspec.matches_path(Path::new(path), flags)
+            }
+            Some(Matcher::Globs(ref globs)) => globs.is_match(path),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Per-file stats within a single commit's diff.
+
+use git2::{Delta, Diff, Error, Tree};
+use serde_derive::Serialize;
+use std::cell::RefCell;
+use std::path::Path;
+
+/// Mode of a tree entry for a submodule's commit.
+const GITLINK_MODE: i32 = 0o160000;
+
+/// Lines changed in one file of a diff.
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct FileStat {
+    pub path: String,
+    #[serde(rename(serialize = "i"))]
+    pub insertions: usize,
+    #[serde(rename(serialize = "d"))]
+    pub deletions: usize,
+    /// Binary files always report zero insertions and deletions.
+    #[serde(rename(serialize = "bin"))]
+    pub binary: bool,
+    /// How the file changed, such as added or deleted.
+    #[serde(skip)]
+    pub status: Delta,
+    /// Number of separate runs of changed lines.
+    #[serde(skip)]
+    pub hunks: usize,
+    /// Whether the file is a submodule, so its change moves the submodule's
+    /// commit. Only set by `mark_submodules`.
+    #[serde(skip)]
+    pub submodule: bool,
+}
+
+/// Collect the stats of every file in a diff, in diff order.
+pub fn file_stats(diff: &Diff) -> Result<Vec<FileStat>, Error> {
+    // Callbacks for a delta always follow the file callback that opened it,
+    // so the last entry is the one being counted
+    let files = RefCell::new(Vec::new());
+    diff.foreach(
+        &mut |delta, _progress| {
+            let path = delta
+                .new_file()
+                .path_bytes()
+                .or_else(|| delta.old_file().path_bytes())
+                .unwrap_or_default();
+            files.borrow_mut().push(FileStat {
+                path: String::from_utf8_lossy(path).into_owned(),
+                insertions: 0,
+                deletions: 0,
+                binary: false,
+                status: delta.status(),
+                hunks: 0,
+                submodule: false,
+            });
+            true
+        },
+        Some(&mut |_delta, _binary| {
+            if let Some(file) = files.borrow_mut().last_mut() {
+                file.binary = true;
+            }
+            true
+        }),
+        Some(&mut |_delta, _hunk| {
+            if let Some(file) = files.borrow_mut().last_mut() {
+                file.hunks += 1;
+            }
+            true
+        }),
+        Some(&mut |_delta, _hunk, line| {
+            if let Some(file) = files.borrow_mut().last_mut() {
+                match line.origin() {
+                    '+' => file.insertions += 1,
+                    '-' => file.deletions += 1,
+                    _ => {}
+                }
+            }
+            true
+        }),
+    )?;
+    Ok(files.into_inner())
+}
+
+/// Mark the files which are submodules in the tree on either side of a diff.
+pub fn mark_submodules(files: &mut [FileStat], old: Option<&Tree>, new: &Tree) {
+    for file in files {
+        let path = Path::new(&file.path);
+        file.submodule = old.into_iter().chain(Some(new)).any(|tree| {
+            tree.get_path(path)
+                .is_ok_and(|entry| entry.filemode() == GITLINK_MODE)
+        });
+    }
+}
+
+/// A file's stats tagged with the commit it was changed in.
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct FileRecord {
+    #[serde(rename(serialize = "c"))]
+    pub id: String,
+    #[serde(flatten)]
+    pub file: FileStat,
+    #[serde(rename(serialize = "h"), skip_serializing_if = "Option::is_none")]
+    pub hunks: Option<usize>,
+}
@@ -0,0 +1,85 @@
+//! Following a single file back through renames, like `git log --follow`.
+//!
+//! libgit2 has no notion of following, so the path is tracked by hand: commits
+//! are seen newest first, and whenever the followed file is added by a commit
+//! which actually renamed it, the old name is followed from then on.
+
+use crate::shallow::Grafts;
+use crate::{diff_options_for, lossy, WalkOptions};
+use git2::{Commit, Delta, DiffFindOptions, DiffOptions, Error, Repository};
+
+/// The path being followed, as of the last commit seen.
+pub struct Follow {
+    path: String,
+    threshold: u16,
+}
+
+impl Follow {
+    pub fn new(path: String, threshold: u16) -> Self {
+        Self { path, threshold }
+    }
+
+    /// Whether `commit` touched the followed file, and if so the paths its
+    /// record should be diffed over. This must see every commit of the walk,
+    /// newest first, to notice each rename.
+    pub fn step(
+        &mut self,
+        repo: &Repository,
+        commit: &Commit,
+        grafts: &Grafts,
+        opts: &WalkOptions,
+    ) -> Result<Option<Vec<String>>, Error> {
+        let tree = commit.tree()?;
+        if grafts.parent_count(commit) == 0 {
+            return Ok(tree
+                .get_path(self.path.as_ref())
+                .ok()
+                .map(|_| vec![self.path.clone()]));
+        }
+        let mut diffopts = diff_options_for(opts, &[&self.path]);
+        let parent = commit.parent(0)?;
+        let diff =
+            repo.diff_tree_to_tree(Some(&parent.tree()?), Some(&tree), Some(&mut diffopts))?;
+        let added = match diff.deltas().next() {
+            Some(delta) => delta.status() == Delta::Added,
+            None => return Ok(None),
+        };
+        // Like any pathspec, a merge only counts if it differs from every parent
+        for parent in commit.parents().skip(1) {
+            let diff =
+                repo.diff_tree_to_tree(Some(&parent.tree()?), Some(&tree), Some(&mut diffopts))?;
+            if diff.deltas().len() == 0 {
+                return Ok(None);
+            }
+        }
+        let path = self.path.clone();
+        if added {
+            if let Some(old) = self.renamed_from(repo, commit)? {
+                self.path = old.clone();
+                return Ok(Some(vec![path, old]));
+            }
+        }
+        Ok(Some(vec![path]))
+    }
+
+    /// The name the followed file had in the first parent of `commit`, if the
+    /// commit renamed it.
+    fn renamed_from(&self, repo: &Repository, commit: &Commit) -> Result<Option<String>, Error> {
+        let parent = commit.parent(0)?;
+        let mut diffopts = DiffOptions::new();
+        let mut diff = repo.diff_tree_to_tree(
+            Some(&parent.tree()?),
+            Some(&commit.tree()?),
+            Some(&mut diffopts),
+        )?;
+        let mut findopts = DiffFindOptions::new();
+        findopts.renames(true).rename_threshold(self.threshold);
+        diff.find_similar(Some(&mut findopts))?;
+        let old = diff
+            .deltas()
+            .filter(|delta| delta.status() == Delta::Renamed)
+            .find(|delta| delta.new_file().path_bytes() == Some(self.path.as_bytes()))
+            .and_then(|delta| delta.old_file().path_bytes().map(lossy));
+        Ok(old)
+    }
+}
@@ -0,0 +1,2430 @@
+//! Dump short stats from git log for a repo.
+//!
+//! Based on: https://github.com/rust-lang/git2-rs/blob/43b8e28ada54120ff55dc550ab177109f162eb10/examples/log.rs#L1
+
+#![deny(warnings)]
+use git2::{Commit, Delta, Diff, DiffFindOptions, DiffOptions, ObjectType, Oid, Repository};
+use git2::{DiffStats, Error, ErrorClass, ErrorCode, Object, Pathspec, PathspecFlags, Signature};
+use regex::Regex;
+use serde_derive::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::error;
+use std::fmt;
+use std::iter;
+use std::ops::{AddAssign, ControlFlow};
+use std::path::Path;
+use std::str;
+use std::str::FromStr;
+use std::thread;
+
+pub mod aggregate;
+pub mod date;
+pub mod encoding;
+mod exclude;
+pub mod files;
+mod follow;
+mod mailmap;
+mod msgpack;
+pub mod output;
+mod pathspec;
+mod shallow;
+
+use pathspec::Globs;
+use shallow::Grafts;
+
+use date::{DateFormat, Time};
+use encoding::Encoding;
+use exclude::{exclusion, Excludes};
+use files::FileStat;
+use follow::Follow;
+use mailmap::Mailmap;
+
+#[derive(Clone, Serialize, Debug, Default, PartialEq)]
+pub struct ShortStat {
+    #[serde(rename(serialize = "f"))]
+    pub files_changed: usize,
+    #[serde(rename(serialize = "i"))]
+    pub insertions: usize,
+    #[serde(rename(serialize = "d"))]
+    pub deletions: usize,
+}
+
+impl From<DiffStats> for ShortStat {
+    fn from(diff_stats: DiffStats) -> Self {
+        Self {
+            files_changed: diff_stats.files_changed(),
+            insertions: diff_stats.insertions(),
+            deletions: diff_stats.deletions(),
+        }
+    }
+}
+
+impl ShortStat {
+    /// Total up per-file stats, counting each as a changed file.
+    pub fn from_files(files: &[FileStat]) -> Self {
+        Self {
+            files_changed: files.len(),
+            insertions: files.iter().map(|file| file.insertions).sum(),
+            deletions: files.iter().map(|file| file.deletions).sum(),
+        }
+    }
+
+    /// Lines touched, insertions plus deletions.
+    pub fn churn(&self) -> usize {
+        self.insertions + self.deletions
+    }
+}
+
+impl AddAssign<&ShortStat> for ShortStat {
+    fn add_assign(&mut self, other: &ShortStat) {
+        self.files_changed += other.files_changed;
+        self.insertions += other.insertions;
+        self.deletions += other.deletions;
+    }
+}
+
+/// Which parent a merge commit is diffed against.
+#[derive(Clone, Copy, Serialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergeDiff {
+    #[default]
+    FirstParent,
+    SecondParent,
+    /// Approximates git's combined diff: the first parent diff, limited to
+    /// files that differ from every parent.
+    Combined,
+}
+
+impl FromStr for MergeDiff {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first-parent" => Ok(MergeDiff::FirstParent),
+            "second-parent" => Ok(MergeDiff::SecondParent),
+            "combined" => Ok(MergeDiff::Combined),
+            _ => Err(Error::from_str(&format!("unknown merge diff mode: {}", s))),
+        }
+    }
+}
+
+/// What records are sorted by before being yielded, largest or newest first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortOutput {
+    CommitDate,
+    AuthorDate,
+    Insertions,
+    Deletions,
+    Churn,
+    Files,
+}
+
+impl FromStr for SortOutput {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "commit-date" => Ok(SortOutput::CommitDate),
+            "author-date" => Ok(SortOutput::AuthorDate),
+            "insertions" => Ok(SortOutput::Insertions),
+            "deletions" => Ok(SortOutput::Deletions),
+            "churn" => Ok(SortOutput::Churn),
+            "files" => Ok(SortOutput::Files),
+            _ => Err(Error::from_str(&format!("unknown output sort: {}", s))),
+        }
+    }
+}
+
+/// Which algorithm lines are diffed with. Counts of insertions and deletions
+/// can differ between them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DiffAlgorithm {
+    #[default]
+    Myers,
+    /// Myers, spending extra time to find the smallest diff.
+    Minimal,
+    Patience,
+}
+
+impl FromStr for DiffAlgorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "myers" | "default" => Ok(DiffAlgorithm::Myers),
+            "minimal" => Ok(DiffAlgorithm::Minimal),
+            "patience" => Ok(DiffAlgorithm::Patience),
+            "histogram" => Err(Error::from_str(
+                "histogram diffs are not supported by libgit2",
+            )),
+            _ => Err(Error::from_str(&format!("unknown diff algorithm: {}", s))),
+        }
+    }
+}
+
+/// Which kinds of file change count towards a commit's stats, written as for
+/// `git log --diff-filter`: any of `A`dded, `C`opied, `D`eleted, `M`odified,
+/// `R`enamed and `T`ype changed. Lowercase letters leave changes out instead.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DiffFilter {
+    include: Vec<Delta>,
+    exclude: Vec<Delta>,
+}
+
+impl DiffFilter {
+    /// Whether a file changed with `status` counts.
+    pub fn keeps(&self, status: Delta) -> bool {
+        (self.include.is_empty() || self.include.contains(&status))
+            && !self.exclude.contains(&status)
+    }
+}
+
+impl FromStr for DiffFilter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = DiffFilter::default();
+        for c in s.chars() {
+            let status = match c.to_ascii_uppercase() {
+                'A' => Delta::Added,
+                'C' => Delta::Copied,
+                'D' => Delta::Deleted,
+                'M' => Delta::Modified,
+                'R' => Delta::Renamed,
+                'T' => Delta::Typechange,
+                _ => return Err(Error::from_str(&format!("unknown diff filter: {}", c))),
+            };
+            if c.is_ascii_uppercase() {
+                filter.include.push(status);
+            } else {
+                filter.exclude.push(status);
+            }
+        }
+        Ok(filter)
+    }
+}
+
+/// Totals across every emitted record, marked so it can't be mistaken for one.
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct Summary {
+    pub summary: bool,
+    #[serde(rename(serialize = "n"))]
+    pub commits: usize,
+    #[serde(flatten)]
+    pub short_stat: ShortStat,
+}
+
+impl Summary {
+    pub fn new() -> Self {
+        Self {
+            summary: true,
+            commits: 0,
+            short_stat: ShortStat::default(),
+        }
+    }
+
+    pub fn add(&mut self, short_stat: &ShortStat) {
+        self.commits += 1;
+        self.short_stat += short_stat;
+    }
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A single output record: a commit's shortstat plus any optional fields.
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct Record {
+    #[serde(flatten)]
+    pub short_stat: ShortStat,
+    #[serde(rename(serialize = "c"), skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename(serialize = "an"), skip_serializing_if = "Option::is_none")]
+    pub author_name: Option<String>,
+    #[serde(rename(serialize = "ae"), skip_serializing_if = "Option::is_none")]
+    pub author_email: Option<String>,
+    #[serde(rename(serialize = "t"), skip_serializing_if = "Option::is_none")]
+    pub time: Option<Time>,
+    #[serde(rename(serialize = "tz"), skip_serializing_if = "Option::is_none")]
+    pub offset_minutes: Option<i32>,
+    /// Seconds between the commit time of the first parent and this commit,
+    /// negative if the clock went backwards, and null for a root commit.
+    #[serde(rename(serialize = "dt"), skip_serializing_if = "Option::is_none")]
+    pub delta_time: Option<Option<i64>>,
+    #[serde(rename(serialize = "b"), skip_serializing_if = "Option::is_none")]
+    pub binary_files: Option<usize>,
+    /// Number of distinct directories holding the changed files, counting the
+    /// top of the tree as one.
+    #[serde(rename(serialize = "dirs"), skip_serializing_if = "Option::is_none")]
+    pub dirs: Option<usize>,
+    /// Insertions less deletions, negative where a commit shrank the code.
+    #[serde(rename(serialize = "net"), skip_serializing_if = "Option::is_none")]
+    pub net: Option<i64>,
+    /// Insertions plus deletions, the lines a commit touched.
+    #[serde(rename(serialize = "churn"), skip_serializing_if = "Option::is_none")]
+    pub churn: Option<usize>,
+    /// Bytes added to changed blobs less bytes removed.
+    #[serde(rename(serialize = "bytes"), skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<i64>,
+    /// Insertions as a fraction of lines changed, null if none were.
+    #[serde(rename(serialize = "ir"), skip_serializing_if = "Option::is_none")]
+    pub insertion_ratio: Option<Option<f64>>,
+    /// Deletions as a fraction of lines changed, null if none were.
+    #[serde(rename(serialize = "dr"), skip_serializing_if = "Option::is_none")]
+    pub deletion_ratio: Option<Option<f64>>,
+    #[serde(rename(serialize = "p"), skip_serializing_if = "Option::is_none")]
+    pub parents: Option<Vec<String>>,
+    /// Id of the commit's tree, shared by commits with identical contents.
+    #[serde(rename(serialize = "tree"), skip_serializing_if = "Option::is_none")]
+    pub tree: Option<String>,
+    /// Whether the commit has no parents, so is diffed against an empty tree.
+    #[serde(rename(serialize = "root"), skip_serializing_if = "Option::is_none")]
+    pub root: Option<bool>,
+    /// Names of the refs pointing at the commit, omitted if there are none.
+    #[serde(rename(serialize = "refs"), skip_serializing_if = "Option::is_none")]
+    pub refs: Option<Vec<String>>,
+    /// First line of the commit message.
+    #[serde(rename(serialize = "s"), skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    /// Set on merge commits diffed as an approximate combined diff.
+    #[serde(rename(serialize = "m"), skip_serializing_if = "Option::is_none")]
+    pub merge_diff: Option<MergeDiff>,
+    /// Position of the record in the output, counting from zero.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub number: Option<usize>,
+    /// Insertions of this and every record yielded before it.
+    #[serde(rename(serialize = "ci"), skip_serializing_if = "Option::is_none")]
+    pub cumulative_insertions: Option<usize>,
+    /// Deletions of this and every record yielded before it.
+    #[serde(rename(serialize = "cd"), skip_serializing_if = "Option::is_none")]
+    pub cumulative_deletions: Option<usize>,
+    /// Mean insertions of this record and those yielded just before it.
+    #[serde(rename(serialize = "ima"), skip_serializing_if = "Option::is_none")]
+    pub insertions_average: Option<f64>,
+    /// Mean deletions of this record and those yielded just before it.
+    #[serde(rename(serialize = "dma"), skip_serializing_if = "Option::is_none")]
+    pub deletions_average: Option<f64>,
+    #[serde(skip)]
+    pub oid: Oid,
+    /// Per-file stats, only collected when an option needs them.
+    #[serde(skip)]
+    pub files: Vec<FileStat>,
+}
+
+/// An error walking commits or diffing one. The libgit2 error is kept as it
+/// was, code and class alike, with the commit it was diffing kept beside it.
+#[derive(Debug)]
+pub struct RecordError {
+    /// The commit that couldn't be diffed, if the error came from one.
+    pub commit: Option<Oid>,
+    pub error: Error,
+}
+
+impl From<Error> for RecordError {
+    fn from(error: Error) -> Self {
+        Self {
+            commit: None,
+            error,
+        }
+    }
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.commit {
+            Some(commit) => write!(f, "commit {}: {}", commit, self.error.message()),
+            None => f.write_str(self.error.message()),
+        }
+    }
+}
+
+impl error::Error for RecordError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl Record {
+    /// The value of one of `COLUMNS`, if this record carries it.
+    pub fn value(&self, column: &str) -> Option<String> {
+        match column {
+            "files_changed" => Some(self.short_stat.files_changed.to_string()),
+            "insertions" => Some(self.short_stat.insertions.to_string()),
+            "deletions" => Some(self.short_stat.deletions.to_string()),
+            "id" => self.id.clone(),
+            "author_name" => self.author_name.clone(),
+            "author_email" => self.author_email.clone(),
+            "time" => self.time.as_ref().map(|t| t.to_string()),
+            "offset_minutes" => self.offset_minutes.map(|tz| tz.to_string()),
+            "delta_time" => self.delta_time.flatten().map(|dt| dt.to_string()),
+            "binary_files" => self.binary_files.map(|b| b.to_string()),
+            "dirs" => self.dirs.map(|dirs| dirs.to_string()),
+            "net" => self.net.map(|net| net.to_string()),
+            "churn" => self.churn.map(|churn| churn.to_string()),
+            "bytes" => self.bytes.map(|bytes| bytes.to_string()),
+            "insertion_ratio" => self.insertion_ratio.flatten().map(|ir| ir.to_string()),
+            "deletion_ratio" => self.deletion_ratio.flatten().map(|dr| dr.to_string()),
+            "parents" => self.parents.as_ref().map(|parents| parents.join(" ")),
+            "tree" => self.tree.clone(),
+            "root" => self.root.map(|root| root.to_string()),
+            "refs" => self.refs.as_ref().map(|refs| refs.join(" ")),
+            "subject" => self.subject.clone(),
+            "number" => self.number.map(|number| number.to_string()),
+            "cumulative_insertions" => self.cumulative_insertions.map(|ci| ci.to_string()),
+            "cumulative_deletions" => self.cumulative_deletions.map(|cd| cd.to_string()),
+            "insertions_average" => self.insertions_average.map(|ima| ima.to_string()),
+            "deletions_average" => self.deletions_average.map(|dma| dma.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Names of every field a record can carry, in a stable order for tabular
+/// output.
+pub const COLUMNS: &[&str] = &[
+    "files_changed",
+    "insertions",
+    "deletions",
+    "id",
+    "author_name",
+    "author_email",
+    "time",
+    "offset_minutes",
+    "delta_time",
+    "binary_files",
+    "dirs",
+    "net",
+    "churn",
+    "bytes",
+    "insertion_ratio",
+    "deletion_ratio",
+    "parents",
+    "tree",
+    "root",
+    "refs",
+    "subject",
+    "number",
+    "cumulative_insertions",
+    "cumulative_deletions",
+    "insertions_average",
+    "deletions_average",
+];
+
+/// The key a column is serialized under in JSON and MessagePack records.
+pub fn field(column: &str) -> Option<&'static str> {
+    let field = match column {
+        "files_changed" => "f",
+        "insertions" => "i",
+        "deletions" => "d",
+        "id" => "c",
+        "author_name" => "an",
+        "author_email" => "ae",
+        "time" => "t",
+        "offset_minutes" => "tz",
+        "delta_time" => "dt",
+        "binary_files" => "b",
+        "dirs" => "dirs",
+        "net" => "net",
+        "churn" => "churn",
+        "bytes" => "bytes",
+        "insertion_ratio" => "ir",
+        "deletion_ratio" => "dr",
+        "parents" => "p",
+        "tree" => "tree",
+        "root" => "root",
+        "refs" => "refs",
+        "subject" => "s",
+        "number" => "number",
+        "cumulative_insertions" => "ci",
+        "cumulative_deletions" => "cd",
+        "insertions_average" => "ima",
+        "deletions_average" => "dma",
+        _ => return None,
+    };
+    Some(field)
+}
+
+/// Options controlling which commits are walked and what each record holds.
+#[derive(Clone, Debug, Default)]
+pub struct WalkOptions {
+    /// Revisions to walk from; `^rev` hides a revision. Defaults to `HEAD`.
+    pub commits: Vec<String>,
+    /// Globs of refs to walk from too, as with `git log --glob`, such as
+    /// `refs/tags/*`. Given any, `HEAD` isn't walked by default.
+    pub ref_globs: Vec<String>,
+    /// Walk from every ref and `HEAD` too, as with `git log --all`.
+    pub all: bool,
+    /// Walk only the commits since each revision forked from this one, hiding
+    /// the merge base of the two.
+    pub merge_base: Option<String>,
+    /// Yield just the commits of `commits` themselves, in the order given,
+    /// rather than walking their history. Each must be a single revision, and
+    /// options choosing which commits to walk, such as `author`, don't apply.
+    pub tips_only: bool,
+    /// Diff every commit against this revision's tree rather than against its
+    /// parent, so each record is the commit's total change from the base.
+    /// Which commits are walked is unchanged.
+    pub base: Option<String>,
+    /// Diff every commit against an empty tree, so each record counts the
+    /// whole of the commit's tree as inserted. This reads every file of every
+    /// commit, so is slow on long histories.
+    pub snapshot: bool,
+    /// Pathspecs a commit must touch to be included. These are glob patterns
+    /// unless `literal_pathspecs` is set. Those starting `:!`, `:^` or
+    /// `:(exclude)` instead leave paths out, of both matching and stats.
+    pub specs: Vec<String>,
+    /// Match pathspecs as plain paths rather than globs.
+    pub literal_pathspecs: bool,
+    /// Match pathspecs as git's `--glob-pathspecs` does, where `*` stops at
+    /// `/` and `**/` matches any number of directories. Otherwise `*` matches
+    /// across directories too.
+    pub pathspec_glob: bool,
+    /// Match pathspecs ignoring case.
+    pub pathspec_icase: bool,
+    /// Fail the walk if a pathspec matches no file of any walked commit,
+    /// rather than just matching no commits. The walk is buffered first to
+    /// check.
+    pub strict_pathspec: bool,
+    /// Follow the single path in `specs` back through renames. The path is
+    /// tracked through the walk in order, so this suits linear history best.
+    pub follow: bool,
+    pub topo_order: bool,
+    /// Sort topologically, then by commit time and id, so the order doesn't
+    /// depend on libgit2 or on the order revisions were given. This buffers
+    /// the whole walk before any commit is yielded.
+    pub stable: bool,
+    /// Sort by author time, newest first. Unlike the other orderings, this
+    /// buffers every matching commit before any are yielded.
+    pub author_date_order: bool,
+    /// Sort by committer time, newest first, as libgit2's time sorting does.
+    /// Commits made in the same second come in ascending id order, after any
+    /// of their children, so the order doesn't depend on libgit2's.
+    pub date_order: bool,
+    /// Yield commits oldest first. As in git, `skip` and `max_count` pick
+    /// commits before they're reversed.
+    pub reverse: bool,
+    /// Follow only the first parent of merge commits.
+    pub first_parent: bool,
+    /// Walk only commits descended from a hidden revision, such as `A` of
+    /// `A..B`, as with `git log --ancestry-path`.
+    pub ancestry_path: bool,
+    /// Walk a shallow clone, treating the commits at its boundary as root
+    /// commits. Otherwise shallow clones are refused.
+    pub allow_shallow: bool,
+    /// Number of matching commits to skip. Every filter applies first, so
+    /// this counts only commits that would otherwise be yielded.
+    pub skip: usize,
+    /// Most commits to yield after skipping, where `Some(0)` yields none and
+    /// `None` is unlimited.
+    pub max_count: Option<usize>,
+    /// Drop commits with fewer insertions than this, once diffed.
+    pub min_insertions: Option<usize>,
+    /// Drop commits with more insertions than this, once diffed.
+    pub max_insertions: Option<usize>,
+    pub min_deletions: Option<usize>,
+    pub max_deletions: Option<usize>,
+    /// Drop commits with fewer insertions plus deletions than this, once
+    /// diffed.
+    pub min_churn: Option<usize>,
+    pub max_churn: Option<usize>,
+    /// Drop commits which change no files, once diffed.
+    pub skip_empty: bool,
+    /// Count only files changed in these ways. Commits are still yielded, all
+    /// zeros if no files count, unless `skip_empty` drops them.
+    pub diff_filter: Option<DiffFilter>,
+    /// Globs, matched as pathspecs are, of generated files to leave out of
+    /// the stats. Unlike excluding pathspecs, commits changing nothing else
+    /// are still yielded, as all zeros.
+    pub ignore_generated: Vec<String>,
+    /// Keep only commits whose every changed file is binary, once diffed.
+    /// Commits changing nothing never match.
+    pub binary_only: bool,
+    /// Leave submodule changes, their gitlinks' commits moving, out of the
+    /// stats.
+    pub ignore_submodules: bool,
+    /// Count only submodule changes, keeping only commits which make some.
+    pub submodules_only: bool,
+    /// Sort records once every one is diffed, rather than yielding them in
+    /// walk order. Ties keep walk order, and skipping and limiting apply to
+    /// the walk first.
+    pub sort_output: Option<SortOutput>,
+    pub min_parents: usize,
+    /// Commits with more parents than this are excluded, so `Some(1)` leaves
+    /// out merges.
+    pub max_parents: Option<usize>,
+    /// Substring to match against the author name or email, ignoring case.
+    pub author: Option<String>,
+    /// Substring to match against the committer name or email, ignoring case.
+    pub committer: Option<String>,
+    /// Regex to match against the author as `Name <email>`, alongside any
+    /// `author` substring.
+    pub author_regex: Option<String>,
+    /// Regex to match against the committer as `Name <email>`.
+    pub committer_regex: Option<String>,
+    /// Regex to match against the commit message.
+    pub grep: Option<String>,
+    /// Include only commits whose message doesn't match `grep`.
+    pub invert_grep: bool,
+    /// Regex at least one path changed by a commit must match, alongside any
+    /// pathspecs. Merges are checked against their first parent.
+    pub path_regex: Option<String>,
+    /// Path whose tree entry must differ from the parents', compared by id
+    /// without diffing. Merges must differ from every parent, unless only
+    /// first parents are followed.
+    pub changed_path: Option<String>,
+    /// Include each commit with this probability, from 0 to 1, decided by its
+    /// id so the same commits are picked every run.
+    pub sample: Option<f64>,
+    /// Earliest commit time to include, in seconds since the epoch.
+    pub since: Option<i64>,
+    /// Latest commit time to include, in seconds since the epoch.
+    pub until: Option<i64>,
+    pub with_id: bool,
+    /// Abbreviate commit ids to at least this many hex digits, as many more
+    /// as keep them unique.
+    pub abbrev: Option<usize>,
+    pub with_author: bool,
+    /// Canonicalize author names and emails with the repository's mailmap,
+    /// both in records and when filtering by author or committer.
+    pub use_mailmap: bool,
+    pub with_time: bool,
+    /// How `Record::time` is written.
+    pub date_format: DateFormat,
+    /// Include the seconds since the first parent was committed.
+    pub with_delta_time: bool,
+    /// Count binary files, which never report insertions or deletions.
+    pub with_binary_count: bool,
+    /// Count the distinct directories of the files each commit changed.
+    pub with_dirs: bool,
+    pub with_net: bool,
+    /// Include insertions plus deletions.
+    pub with_churn: bool,
+    /// Include the change in size of the blobs each commit changed, which
+    /// counts binary files as lines can't.
+    pub with_bytes: bool,
+    /// Include insertions and deletions as fractions of the lines changed.
+    pub with_ratios: bool,
+    pub with_parents: bool,
+    pub with_tree: bool,
+    pub with_root_flag: bool,
+    /// Include the names of refs pointing at each commit, including `HEAD`.
+    pub decorate: bool,
+    pub with_subject: bool,
+    /// Truncate subjects to this many characters.
+    pub subject_maxlen: Option<usize>,
+    /// Decode messages and signatures from this encoding, rather than the
+    /// one each commit declares, or UTF-8 if it declares none.
+    pub encoding: Option<Encoding>,
+    /// Number records in the order they're yielded, after any skipping.
+    pub number: bool,
+    /// Total insertions and deletions so far in each record, in the order
+    /// they're yielded.
+    pub cumulative: bool,
+    /// Average insertions and deletions over this many records in each, the
+    /// record and those yielded just before it. Until there are that many,
+    /// all so far are averaged.
+    pub window: Option<usize>,
+    pub ignore_whitespace: bool,
+    pub ignore_whitespace_change: bool,
+    pub ignore_whitespace_eol: bool,
+    pub diff_algorithm: DiffAlgorithm,
+    /// Lines of context around each hunk, defaulting to libgit2's three.
+    pub context_lines: Option<u32>,
+    /// Unchanged lines between hunks that still merge them into one.
+    pub interhunk_lines: Option<u32>,
+    /// How merge commits are diffed. Ignored when following first parents.
+    pub merge_diff: MergeDiff,
+    /// Detect renames at this similarity percentage, so a renamed file counts
+    /// only its edited lines rather than a full deletion and insertion.
+    pub find_renames: Option<u16>,
+    /// Collect per-file stats into `Record::files`.
+    pub per_file: bool,
+    /// Number of threads to compute diffs on. Zero or one diffs serially.
+    pub jobs: usize,
+    /// An object directory the repository was given as an alternate, with
+    /// `Odb::add_disk_alternate`. Threads diffing with their own handle on
+    /// the repository add it to theirs.
+    pub object_dir: Option<String>,
+}
+
+impl WalkOptions {
+    /// Names of the fields each record carries, in `COLUMNS` order.
+    pub fn columns(&self) -> Vec<&'static str> {
+        COLUMNS
+            .iter()
+            .cloned()
+            .filter(|column| self.has_column(column))
+            .collect()
+    }
+
+    fn has_column(&self, column: &str) -> bool {
+        match column {
+            "files_changed" | "insertions" | "deletions" => true,
+            "id" => self.with_id,
+            "author_name" | "author_email" => self.with_author,
+            "time" | "offset_minutes" => self.with_time,
+            "delta_time" => self.with_delta_time,
+            "binary_files" => self.with_binary_count,
+            "dirs" => self.with_dirs,
+            "net" => self.with_net,
+            "churn" => self.with_churn,
+            "bytes" => self.with_bytes,
+            "insertion_ratio" | "deletion_ratio" => self.with_ratios,
+            "parents" => self.with_parents,
+            "tree" => self.with_tree,
+            "root" => self.with_root_flag,
+            "refs" => self.decorate,
+            "subject" => self.with_subject,
+            "number" => self.number,
+            "cumulative_insertions" | "cumulative_deletions" => self.cumulative,
+            "insertions_average" | "deletions_average" => self.window.is_some(),
+            _ => false,
+        }
+    }
+
+    /// Resolve `specs`, given in the directory `prefix` of the working tree,
+    /// to pathspecs from the top of it, as git does when run in a
+    /// subdirectory.
+    pub fn resolve_specs(&mut self, prefix: &str) -> Result<(), Error> {
+        self.specs = self
+            .specs
+            .iter()
+            .map(|spec| pathspec::from_top(prefix, spec, self.literal_pathspecs))
+            .collect::<Result<_, _>>()?;
+        Ok(())
+    }
+
+    /// Set whichever option makes records carry one of `COLUMNS`.
+    pub fn enable_column(&mut self, column: &str) {
+        match column {
+            "id" => self.with_id = true,
+            "author_name" | "author_email" => self.with_author = true,
+            "time" | "offset_minutes" => self.with_time = true,
+            "delta_time" => self.with_delta_time = true,
+            "binary_files" => self.with_binary_count = true,
+            "dirs" => self.with_dirs = true,
+            "net" => self.with_net = true,
+            "churn" => self.with_churn = true,
+            "bytes" => self.with_bytes = true,
+            "insertion_ratio" | "deletion_ratio" => self.with_ratios = true,
+            "parents" => self.with_parents = true,
+            "tree" => self.with_tree = true,
+            "root" => self.with_root_flag = true,
+            "refs" => self.decorate = true,
+            "subject" => self.with_subject = true,
+            "number" => self.number = true,
+            "cumulative_insertions" | "cumulative_deletions" => self.cumulative = true,
+            "insertions_average" | "deletions_average" => {
+                self.window.get_or_insert(1);
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether refs are walked from by pattern, so `HEAD` isn't by default.
+    fn selects_refs(&self) -> bool {
+        self.all || !self.ref_globs.is_empty()
+    }
+
+    /// Whether commits can only be matched once diffed, so walking alone
+    /// can't tell which are included.
+    pub fn filters_stats(&self) -> bool {
+        self.min_insertions.is_some()
+            || self.max_insertions.is_some()
+            || self.min_deletions.is_some()
+            || self.max_deletions.is_some()
+            || self.min_churn.is_some()
+            || self.max_churn.is_some()
+            || self.skip_empty
+            || self.binary_only
+            || self.submodules_only
+    }
+
+    /// Whether commits are reversed once skipped and limited, as git does,
+    /// rather than as they're walked, so `--reverse -n 2` is the two newest.
+    fn reverses_limited(&self) -> bool {
+        self.reverse && (self.skip > 0 || self.max_count.is_some())
+    }
+
+    /// Whether a diffed commit's stats are within the limits set.
+    // `Option::is_none_or` is too new to rely on
+    #[allow(unknown_lints, clippy::unnecessary_map_or)]
+    fn stats_match(&self, record: &Record) -> bool {
+        let short_stat = &record.short_stat;
+        let within = |n: usize, min: Option<usize>, max: Option<usize>| {
+            min.map_or(true, |min| n >= min) && max.map_or(true, |max| n <= max)
+        };
+        within(
+            short_stat.insertions,
+            self.min_insertions,
+            self.max_insertions,
+        ) && within(short_stat.deletions, self.min_deletions, self.max_deletions)
+            && within(short_stat.churn(), self.min_churn, self.max_churn)
+            && !(self.skip_empty && *short_stat == ShortStat::default())
+            && !(self.binary_only
+                && (record.files.is_empty() || record.files.iter().any(|file| !file.binary)))
+            && !(self.submodules_only && record.files.is_empty())
+    }
+
+    /// Whether a changed file counts towards a commit's stats, matching the
+    /// glob pathspecs if any, neither excluded nor generated, and kept by the
+    /// diff filter.
+    // `Option::is_none_or` is too new to rely on
+    #[allow(unknown_lints, clippy::unnecessary_map_or)]
+    fn keeps_file(
+        &self,
+        file: &FileStat,
+        globs: Option<&Globs>,
+        excludes: &Excludes,
+        generated: &Excludes,
+    ) -> bool {
+        globs.map_or(true, |globs| globs.is_match(&file.path))
+            && !excludes.excludes(&file.path)
+            && !generated.excludes(&file.path)
+            && self
+                .diff_filter
+                .as_ref()
+                .map_or(true, |filter| filter.keeps(file.status))
+            && (!self.ignore_submodules || !file.submodule)
+            && (!self.submodules_only || file.submodule)
+    }
+
+    /// Whether some changed files may not count towards a commit's stats,
+    /// besides those left out by pathspec.
+    fn filters_files(&self) -> bool {
+        self.diff_filter.is_some()
+            || self.ignore_submodules
+            || self.submodules_only
+            || !self.ignore_generated.is_empty()
+    }
+
+    /// Whether records need per-file stats collecting.
+    fn needs_files(&self) -> bool {
+        self.per_file || self.with_binary_count || self.binary_only || self.with_dirs
+    }
+
+    /// Pathspecs a commit must touch, without any exclusions.
+    /// libgit2 matches nothing for a pathspec of the whole tree, such as `.`,
+    /// so if there's one none are kept, leaving every path matched.
+    fn included_specs(&self) -> Vec<&str> {
+        let specs: Vec<_> = self
+            .specs
+            .iter()
+            .map(|spec| &spec[..])
+            .filter(|spec| exclusion(spec, self.literal_pathspecs).is_none())
+            .collect();
+        if specs.contains(&".") {
+            Vec::new()
+        } else {
+            specs
+        }
+    }
+
+    /// Similarity percentage to detect renames at, if any.
+    fn rename_threshold(&self) -> Option<u16> {
+        match self.find_renames {
+            Some(threshold) => Some(threshold),
+            None if self.follow => Some(FOLLOW_RENAME_THRESHOLD),
+            None => None,
+        }
+    }
+
+    /// How a commit with this many parents is diffed.
+    fn merge_diff_for(&self, parents: usize) -> MergeDiff {
+        if parents > 1 && !self.first_parent {
+            self.merge_diff
+        } else {
+            MergeDiff::FirstParent
+        }
+    }
+}
+
+/// Walk the history of `repo` and yield a record for every matching commit.
+///
+/// Merge commits are diffed against their first parent, root commits against
+/// the empty tree. Only objects are read, so `repo` may be bare.
+///
+/// ```
+/// use git2::{Repository, Signature};
+/// use shortstat_dump::{shortstats, WalkOptions};
+/// use std::{env, fs, path::Path, process};
+///
+/// // A repository with one commit adding two lines
+/// let dir = env::temp_dir().join(format!("shortstats-doc-{}", process::id()));
+/// let repo = Repository::init(&dir)?;
+/// fs::write(dir.join("a"), "1\n2\n").unwrap();
+/// let mut index = repo.index()?;
+/// index.add_path(Path::new("a"))?;
+/// let tree = repo.find_tree(index.write_tree()?)?;
+/// let alice = Signature::now("Alice", "alice@example.com")?;
+/// repo.commit(Some("HEAD"), &alice, &alice, "add a", &tree, &[])?;
+///
+/// let opts = WalkOptions {
+///     max_count: Some(10),
+///     ..WalkOptions::default()
+/// };
+/// let records = shortstats(&repo, &opts)?.collect::<Result<Vec<_>, _>>()?;
+/// assert_eq!(records.len(), 1);
+/// assert_eq!(records[0].short_stat.files_changed, 1);
+/// assert_eq!(records[0].short_stat.insertions, 2);
+/// # fs::remove_dir_all(&dir).unwrap();
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn shortstats<'r>(
+    repo: &'r Repository,
+    opts: &'r WalkOptions,
+) -> Result<impl Iterator<Item = Result<Record, RecordError>> + 'r, Error> {
+    // Skipping and limiting apply to commits left after any filtering on
+    // stats, which needs every commit diffing. Otherwise, diff only those kept
+    let (skip, max_count) = (opts.skip, opts.max_count.unwrap_or(usize::MAX));
+    let (walk_skip, walk_max_count, skip, max_count) = if opts.filters_stats() {
+        (0, usize::MAX, skip, max_count)
+    } else {
+        (skip, max_count, 0, usize::MAX)
+    };
+    let reverse = opts.reverse && !opts.reverses_limited();
+    let mut revwalk = walk(repo, opts, reverse)?
+        .skip(walk_skip)
+        .take(walk_max_count);
+    let mut diffopts = diff_options(opts);
+    let globs = globs(opts)?;
+    let grafts = Grafts::load(repo)?;
+    // Resolve the base once, so it can't move under a long walk
+    let base = match opts.base {
+        Some(ref base) => Some(Base::Tree(repo.revparse_single(base)?.peel_to_tree()?.id())),
+        None if opts.snapshot => Some(Base::Empty),
+        None => None,
+    };
+
+    // Diff in batches, spread over worker threads when asked to
+    let batch_size = if opts.jobs > 1 {
+        opts.jobs * BATCH_PER_JOB
+    } else {
+        1
+    };
+    let batches = std::iter::from_fn(move || {
+        let batch: Vec<_> = revwalk.by_ref().take(batch_size).collect();
+        if batch.is_empty() {
+            None
+        } else {
+            Some(batch)
+        }
+    });
+    let records = batches.flat_map(move |batch| {
+        if opts.jobs > 1 {
+            let ids = batch
+                .into_iter()
+                .map(|commit| commit.map(|(c, paths)| (c.id(), paths)));
+            records_parallel(
+                repo.path(),
+                ids.collect(),
+                &grafts,
+                base,
+                opts,
+                globs.as_ref(),
+            )
+        } else {
+            batch
+                .into_iter()
+                .map(|commit| {
+                    let (commit, paths) = commit?;
+                    let globs = globs.as_ref();
+                    match paths {
+                        Some(paths) => {
+                            let mut diffopts = diff_options_for(opts, &paths);
+                            record(repo, &commit, &grafts, base, opts, &mut diffopts, globs)
+                        }
+                        None => record(repo, &commit, &grafts, base, opts, &mut diffopts, globs),
+                    }
+                })
+                .collect()
+        }
+    });
+    let records = records
+        .filter(move |record| match record {
+            Ok(record) => opts.stats_match(record),
+            Err(_) => true,
+        })
+        .skip(skip)
+        .take(max_count);
+    let records: Box<dyn Iterator<Item = Result<Record, RecordError>> + 'r> =
+        if opts.reverses_limited() {
+            Box::new(records.collect::<Vec<_>>().into_iter().rev())
+        } else {
+            Box::new(records)
+        };
+    let records: Box<dyn Iterator<Item = Result<Record, RecordError>> + 'r> = match opts.sort_output
+    {
+        Some(key) => Box::new(sort_records(repo, records.collect(), key).into_iter()),
+        None => Box::new(records),
+    };
+    let decorations = if opts.decorate {
+        decorations(repo)?
+    } else {
+        HashMap::new()
+    };
+    let mailmap = if opts.use_mailmap {
+        Some(Mailmap::load(repo)?)
+    } else {
+        None
+    };
+    let mut total = ShortStat::default();
+    let mut recent = VecDeque::new();
+    Ok(records.enumerate().map(move |(i, record)| {
+        let mut record = record?;
+        if opts.number {
+            record.number = Some(i);
+        }
+        if opts.cumulative {
+            total += &record.short_stat;
+            record.cumulative_insertions = Some(total.insertions);
+            record.cumulative_deletions = Some(total.deletions);
+        }
+        if let Some(window) = opts.window {
+            if recent.len() == window {
+                recent.pop_front();
+            }
+            recent.push_back((record.short_stat.insertions, record.short_stat.deletions));
+            let mean = |sum: usize| sum as f64 / recent.len() as f64;
+            record.insertions_average = Some(mean(recent.iter().map(|(i, _)| i).sum()));
+            record.deletions_average = Some(mean(recent.iter().map(|(_, d)| d).sum()));
+        }
+        record.refs = decorations.get(&record.oid).cloned();
+        if let (Some(mailmap), Some(name), Some(email)) =
+            (&mailmap, &record.author_name, &record.author_email)
+        {
+            let (name, email) = mailmap.resolve(name, email);
+            record.author_name = Some(name);
+            record.author_email = Some(email);
+        }
+        Ok(record)
+    }))
+}
+
+/// Call `f` with the record of every matching commit, as `shortstats` yields
+/// them, until it breaks or fails.
+///
+/// Records are handed over as they're diffed, so none are collected unless an
+/// option needs them all at once. Errors from the walk and from `f` alike end
+/// it and are returned.
+///
+/// ```
+/// use git2::{Error, Repository};
+/// use shortstat_dump::{for_each_shortstat, RecordError, WalkOptions};
+/// use std::ops::ControlFlow;
+///
+/// # let dir = std::env::temp_dir().join(format!("for-each-{}", std::process::id()));
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// # let repo = Repository::init(&dir)?;
+/// # let sig = git2::Signature::now("Alice", "alice@example.com")?;
+/// # let mut parent = None;
+/// # for i in 1..=10 {
+/// #     let mut tree = repo.treebuilder(None)?;
+/// #     tree.insert("lines", repo.blob("x\n".repeat(i).as_bytes())?, 0o100644)?;
+/// #     let tree = repo.find_tree(tree.write()?)?;
+/// #     let parents: Vec<_> = parent.iter().collect();
+/// #     let id = repo.commit(Some("HEAD"), &sig, &sig, "commit", &tree, &parents)?;
+/// #     parent = Some(repo.find_commit(id)?);
+/// # }
+/// # drop(parent);
+/// # let repo = Repository::open(&dir)?;
+/// let opts = WalkOptions::default();
+/// let mut seen = 0;
+/// for_each_shortstat(&repo, &opts, |record| {
+///     println!("{:?}", record.short_stat);
+///     seen += 1;
+///     Ok::<_, RecordError>(if seen == 5 {
+///         ControlFlow::Break(())
+///     } else {
+///         ControlFlow::Continue(())
+///     })
+/// })?;
+/// assert_eq!(seen, 5);
+///
+/// // An error from the closure ends the walk, and is returned
+/// let failed = for_each_shortstat(&repo, &opts, |_| Err(RecordError::from(Error::from_str("stop"))));
+/// assert_eq!(failed.unwrap_err().error.message(), "stop");
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// # Ok::<(), RecordError>(())
+/// ```
+pub fn for_each_shortstat<F, E>(repo: &Repository, opts: &WalkOptions, mut f: F) -> Result<(), E>
+where
+    F: FnMut(Record) -> Result<ControlFlow<()>, E>,
+    E: From<Error> + From<RecordError>,
+{
+    for record in shortstats(repo, opts)? {
+        if f(record?)?.is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The stats of uncommitted changes, staged or not, against `HEAD`, as for
+/// `git diff HEAD`. Untracked files aren't counted, and a clean tree is all
+/// zeros. Only the pathspec and diff options of `opts` apply.
+pub fn worktree_shortstat(repo: &Repository, opts: &WalkOptions) -> Result<ShortStat, Error> {
+    if repo.is_bare() {
+        return Err(Error::from_str(
+            "--worktree needs a working tree, but the repository is bare: pass --work-tree",
+        ));
+    }
+    let head = match repo.head() {
+        Ok(head) => Some(head.peel_to_tree()?),
+        Err(ref e) if e.code() == ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(e),
+    };
+    let mut diffopts = diff_options(opts);
+    let mut diff = repo.diff_tree_to_workdir_with_index(head.as_ref(), Some(&mut diffopts))?;
+    if let Some(threshold) = opts.rename_threshold() {
+        let mut findopts = DiffFindOptions::new();
+        findopts.renames(true).rename_threshold(threshold);
+        diff.find_similar(Some(&mut findopts))?;
+    }
+    let globs = globs(opts)?;
+    let excludes = Excludes::new(opts)?;
+    let generated = Excludes::globs(&opts.ignore_generated)?;
+    if globs.is_none() && excludes.is_empty() && generated.is_empty() && opts.diff_filter.is_none()
+    {
+        return Ok(diff.stats()?.into());
+    }
+    let mut files = files::file_stats(&diff)?;
+    files.retain(|file| opts.keeps_file(file, globs.as_ref(), &excludes, &generated));
+    Ok(ShortStat::from_files(&files))
+}
+
+/// Walk the history of `repo` and yield every matching commit, without
+/// diffing any beyond what pathspec matching needs.
+pub fn commits<'r>(
+    repo: &'r Repository,
+    opts: &'r WalkOptions,
+) -> Result<impl Iterator<Item = Result<Commit<'r>, Error>> + 'r, Error> {
+    let reverse = opts.reverse && !opts.reverses_limited();
+    let commits = walk(repo, opts, reverse)?
+        .map(|commit| commit.map(|(commit, _)| commit))
+        .skip(opts.skip)
+        .take(opts.max_count.unwrap_or(usize::MAX));
+    let commits: Box<dyn Iterator<Item = Result<Commit<'r>, Error>> + 'r> =
+        if opts.reverses_limited() {
+            Box::new(commits.collect::<Vec<_>>().into_iter().rev())
+        } else {
+            Box::new(commits)
+        };
+    Ok(commits)
+}
+
+/// Check everything a walk of `repo` would need to resolve, without walking:
+/// every revision, pathspec and pattern of `opts`. Returns every problem found,
+/// so none are missed behind the first.
+pub fn validate(repo: &Repository, opts: &WalkOptions) -> Vec<Error> {
+    let mut errors = Vec::new();
+    let unknown = |rev: &str, e: Error| {
+        Error::from_str(&format!("unknown revision {}: {}", rev, e.message()))
+    };
+    for commit in &opts.commits {
+        let resolved = match commit.strip_prefix('^') {
+            Some(hidden) => repo
+                .revparse_single(hidden)
+                .and_then(|obj| obj.peel_to_commit())
+                .map(|_| ()),
+            // Tags peel to the commits they tag, but not every object does
+            None => repo.revparse(commit).and_then(|revspec| {
+                for obj in revspec.from().into_iter().chain(revspec.to()) {
+                    obj.peel_to_commit()?;
+                }
+                Ok(())
+            }),
+        };
+        if let Err(e) = resolved {
+            errors.push(unknown(commit, e));
+        }
+    }
+    if let Some(ref base) = opts.merge_base {
+        if let Err(e) = repo
+            .revparse_single(base)
+            .and_then(|obj| obj.peel_to_commit())
+        {
+            errors.push(unknown(base, e));
+        }
+    }
+    if let Some(ref base) = opts.base {
+        if let Err(e) = repo
+            .revparse_single(base)
+            .and_then(|obj| obj.peel_to_tree())
+        {
+            errors.push(unknown(base, e));
+        }
+    }
+    if let Err(e) = ref_tips(repo, opts) {
+        errors.push(e);
+    }
+    if opts.commits.is_empty() && !opts.selects_refs() {
+        if let Err(e) = repo.head().and_then(|head| head.peel_to_commit()) {
+            errors.push(e);
+        }
+    }
+    if opts.follow && opts.specs.len() != 1 {
+        errors.push(Error::from_str("--follow needs exactly one pathspec"));
+    }
+    if repo.is_shallow() && !opts.allow_shallow {
+        errors.push(Error::from_str(
+            "repository is shallow, so its history is incomplete",
+        ));
+    }
+    if let Err(e) = Pathspec::new(opts.included_specs()) {
+        errors.push(e);
+    }
+    if let Err(e) = globs(opts) {
+        errors.push(e);
+    }
+    if let Err(e) = Excludes::new(opts) {
+        errors.push(e);
+    }
+    if let Err(e) = Excludes::globs(&opts.ignore_generated) {
+        errors.push(e);
+    }
+    let patterns = [
+        &opts.grep,
+        &opts.path_regex,
+        &opts.author_regex,
+        &opts.committer_regex,
+    ];
+    for pattern in patterns.iter().filter_map(|pattern| pattern.as_ref()) {
+        if let Err(e) = Regex::new(pattern) {
+            errors.push(Error::from_str(&e.to_string()));
+        }
+    }
+    errors
+}
+
+/// Paths to diff a commit over, when they differ from the walk's pathspecs.
+type Paths = Option<Vec<String>>;
+
+/// A commit of the walk, with the paths to diff it over.
+type Walked<'r> = (Commit<'r>, Paths);
+
+/// Walk the history of `repo`, as for `commits` but without skipping or
+/// limiting the matched commits, and oldest first if `reverse` rather than as
+/// `opts` says.
+fn walk<'r>(
+    repo: &'r Repository,
+    opts: &'r WalkOptions,
+    reverse: bool,
+) -> Result<impl Iterator<Item = Result<Walked<'r>, Error>> + 'r, Error> {
+    let mut revwalk = repo.revwalk()?;
+    if opts.follow && opts.specs.len() != 1 {
+        return Err(Error::from_str("--follow needs exactly one pathspec"));
+    }
+    if opts.tips_only {
+        let tips = tips(repo, opts)?
+            .into_iter()
+            .map(|commit| Ok((commit, None)));
+        let tips: Box<dyn Iterator<Item = Result<Walked<'r>, Error>> + 'r> = Box::new(tips);
+        return Ok(tips);
+    }
+    let shallow = repo.is_shallow();
+    if shallow && !opts.allow_shallow {
+        return Err(Error::from_str(
+            "repository is shallow, so its history is incomplete: run git fetch --unshallow, \
+             or pass --allow-shallow to walk what there is",
+        ));
+    }
+
+    // Prepare the revwalk based on the options. libgit2 reverses the whole
+    // sorted walk, as git does, so a reversed topological walk is exactly the
+    // topological walk backwards. Following must see commits newest first, so
+    // any reversing is done afterwards
+    let base = if reverse && !opts.follow {
+        git2::Sort::REVERSE
+    } else {
+        git2::Sort::NONE
+    };
+    let sorting = base
+        | if opts.stable {
+            git2::Sort::TOPOLOGICAL | git2::Sort::TIME
+        } else if opts.topo_order || opts.author_date_order {
+            git2::Sort::TOPOLOGICAL
+        } else if opts.date_order {
+            git2::Sort::TIME
+        } else {
+            git2::Sort::NONE
+        };
+    // Stable orders are sorted by hand, once the whole walk is seen
+    revwalk.set_sorting(if shallow || opts.stable {
+        git2::Sort::NONE
+    } else {
+        sorting
+    });
+    if opts.first_parent {
+        revwalk.simplify_first_parent();
+    }
+    // Peel annotated tags to the commits they tag, as the tips are used as
+    // commits rather than just pushed. The commits pushed and hidden are kept,
+    // to walk shallow clones by hand
+    let peel = |obj: &Object| obj.peel(ObjectType::Commit).map(|commit| commit.id());
+    let mut tips = Vec::new();
+    // Hidden commits, which ancestry paths lead from too
+    let mut hidden = Vec::new();
+    for commit in &opts.commits {
+        if let Some(rev) = commit.strip_prefix('^') {
+            hidden.push(peel(&repo.revparse_single(rev)?)?);
+            continue;
+        }
+        let revspec = repo.revparse(commit)?;
+        if revspec.mode().contains(git2::RevparseMode::SINGLE) {
+            tips.push(peel(revspec.from().unwrap())?);
+        } else {
+            let from = peel(revspec.from().unwrap())?;
+            let to = peel(revspec.to().unwrap())?;
+            tips.push(to);
+            if revspec.mode().contains(git2::RevparseMode::MERGE_BASE) {
+                // `A...B` is the commits of either side but not both, so
+                // hides what they have in common rather than `A`. Unrelated
+                // histories have nothing in common
+                tips.push(from);
+                match repo.merge_bases(from, to) {
+                    Ok(bases) => hidden.extend(bases.iter()),
+                    Err(ref e) if e.code() == ErrorCode::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            } else {
+                hidden.push(from);
+            }
+        }
+    }
+    tips.extend(ref_tips(repo, opts)?.iter().map(Commit::id));
+    if opts.commits.is_empty() && !opts.selects_refs() {
+        tips.push(repo.head()?.peel_to_commit()?.id());
+    }
+    if let Some(ref base) = opts.merge_base {
+        let base = repo.revparse_single(base)?.peel_to_commit()?.id();
+        for &tip in &tips {
+            let fork = repo.merge_base(base, tip).map_err(|e| match e.code() {
+                ErrorCode::NotFound => {
+                    Error::from_str(&format!("no merge base between {} and {}", base, tip))
+                }
+                _ => e,
+            })?;
+            hidden.push(fork);
+        }
+    }
+    if opts.ancestry_path && hidden.is_empty() {
+        return Err(Error::from_str(
+            "--ancestry-path needs a range or ^rev to find paths from",
+        ));
+    }
+    for &id in &tips {
+        revwalk.push(id)?;
+    }
+    for &id in &hidden {
+        revwalk.hide(id)?;
+    }
+
+    // Prepare our diff options and pathspec matcher
+    let mut diffopts = diff_options(opts);
+    let included = opts.included_specs();
+    let ps = Pathspec::new(&included)?;
+    let globs = globs(opts)?;
+    let excludes = Excludes::new(opts)?;
+    let mut follow = match (opts.follow, opts.rename_threshold()) {
+        (true, Some(threshold)) => Some(Follow::new(opts.specs[0].clone(), threshold)),
+        _ => None,
+    };
+    let regex = |pattern: &Option<String>| match pattern {
+        Some(ref pattern) => Regex::new(pattern)
+            .map(Some)
+            .map_err(|e| Error::from_str(&e.to_string())),
+        None => Ok(None),
+    };
+    let grep = regex(&opts.grep)?;
+    let author_regex = regex(&opts.author_regex)?;
+    let committer_regex = regex(&opts.committer_regex)?;
+    let path_regex = regex(&opts.path_regex)?;
+    // Authors and committers are matched by their canonical identities
+    let mailmap = if opts.use_mailmap {
+        Some(Mailmap::load(repo)?)
+    } else {
+        None
+    };
+
+    // Filter our revwalk based on the options
+    macro_rules! filter_try {
+        ($e:expr) => {
+            match $e {
+                Ok(t) => t,
+                Err(e) => return Some(Err(e)),
+            }
+        };
+    }
+    // libgit2 fails on the missing parents of the commits at the shallow
+    // boundary, so shallow clones are walked by hand, treating those as root
+    // commits, then sorted by hand too. Stable orders are sorted by hand once
+    // the whole walk is seen
+    let grafts = Grafts::load(repo)?;
+    let reversed = sorting.contains(git2::Sort::REVERSE);
+    let revwalk: Box<dyn Iterator<Item = Result<Oid, Error>> + 'r> = if shallow {
+        let ids = shallow::walk(repo, &tips, &hidden, &grafts, opts.first_parent)?;
+        if opts.stable {
+            Box::new(sort_stable(repo, ids, reversed)?.into_iter().map(Ok))
+        } else {
+            Box::new(sort_shallow(repo, ids, sorting)?.into_iter().map(Ok))
+        }
+    } else if opts.stable {
+        let ids = revwalk.collect::<Result<Vec<_>, Error>>()?;
+        Box::new(sort_stable(repo, ids, reversed)?.into_iter().map(Ok))
+    } else {
+        Box::new(revwalk)
+    };
+    let revwalk = if sorting & !git2::Sort::REVERSE == git2::Sort::TIME {
+        Box::new(break_ties(repo, revwalk, reverse))
+    } else {
+        revwalk
+    };
+    let revwalk = if opts.ancestry_path {
+        let ids = revwalk.collect::<Result<Vec<_>, Error>>()?;
+        let descendants = descendants(repo, &ids, &hidden, opts.first_parent)?;
+        Box::new(
+            ids.into_iter()
+                .filter(move |id| descendants.contains(id))
+                .map(Ok),
+        )
+    } else {
+        revwalk
+    };
+    let revwalk = if opts.strict_pathspec && !opts.included_specs().is_empty() {
+        let ids = revwalk.collect::<Result<Vec<_>, Error>>()?;
+        check_pathspecs_match(repo, &ids, opts)?;
+        Box::new(ids.into_iter().map(Ok))
+    } else {
+        revwalk
+    };
+    let revwalk = revwalk.filter_map(move |id| {
+        let id = filter_try!(id);
+        let commit = filter_try!(repo.find_commit(id));
+        // Renames can happen in any commit, so these are all seen first
+        let paths = match follow {
+            Some(ref mut follow) => match filter_try!(follow.step(repo, &commit, &grafts, opts)) {
+                Some(paths) => Some(paths),
+                None => return None,
+            },
+            None => None,
+        };
+        if opts.sample.is_some_and(|rate| !sampled(commit.id(), rate)) {
+            return None;
+        }
+        let parents = grafts.parent_count(&commit);
+        if parents < opts.min_parents {
+            return None;
+        }
+        if let Some(n) = opts.max_parents {
+            if parents > n {
+                return None;
+            }
+        }
+        let mailmap = mailmap.as_ref();
+        let encoding = encoding_of(&commit, opts);
+        if let Some(ref author) = opts.author {
+            if !signature_matches(&identity(&commit.author(), encoding, mailmap), author) {
+                return None;
+            }
+        }
+        if let Some(ref committer) = opts.committer {
+            if !signature_matches(&identity(&commit.committer(), encoding, mailmap), committer) {
+                return None;
+            }
+        }
+        if let Some(ref author_regex) = author_regex {
+            if !author_regex.is_match(&written(&identity(&commit.author(), encoding, mailmap))) {
+                return None;
+            }
+        }
+        if let Some(ref committer_regex) = committer_regex {
+            if !committer_regex.is_match(&written(&identity(
+                &commit.committer(),
+                encoding,
+                mailmap,
+            ))) {
+                return None;
+            }
+        }
+        if let Some(ref grep) = grep {
+            if grep.is_match(&encoding.decode(commit.message_bytes())) == opts.invert_grep {
+                return None;
+            }
+        }
+        let time = commit.time().seconds();
+        if opts.since.is_some_and(|since| time < since)
+            || opts.until.is_some_and(|until| time > until)
+        {
+            return None;
+        }
+        if !opts.specs.is_empty() && !opts.follow {
+            match parents {
+                0 => {
+                    let tree = filter_try!(commit.tree());
+                    let matched = if globs.is_none() && excludes.is_empty() {
+                        let flags = pathspec_flags(opts) | PathspecFlags::NO_MATCH_ERROR;
+                        included.is_empty() || ps.match_tree(&tree, flags).is_ok()
+                    } else {
+                        let diff = repo.diff_tree_to_tree(None, Some(&tree), Some(&mut diffopts));
+                        touches(&filter_try!(diff), globs.as_ref(), &excludes)
+                    };
+                    if !matched {
+                        return None;
+                    }
+                }
+                _ if opts.first_parent => {
+                    let parent = filter_try!(commit.parent(0));
+                    let globs = globs.as_ref();
+                    if !filter_try!(match_with_parent(
+                        repo,
+                        &commit,
+                        &parent,
+                        &mut diffopts,
+                        globs,
+                        &excludes
+                    )) {
+                        return None;
+                    }
+                }
+                _ => {
+                    let m = commit.parents().all(|parent| {
+                        let globs = globs.as_ref();
+                        match_with_parent(repo, &commit, &parent, &mut diffopts, globs, &excludes)
+                            .unwrap_or(false)
+                    });
+                    if !m {
+                        return None;
+                    }
+                }
+            }
+        }
+        if let Some(ref path) = opts.changed_path {
+            let path = Path::new(path.trim_end_matches('/'));
+            let id = filter_try!(entry_id(&commit, path));
+            let changed = if opts.first_parent && parents > 0 {
+                id != filter_try!(entry_id(&filter_try!(commit.parent(0)), path))
+            } else if parents == 0 {
+                id.is_some()
+            } else {
+                let mut changed = true;
+                for parent in commit.parents() {
+                    changed &= id != filter_try!(entry_id(&parent, path));
+                }
+                changed
+            };
+            if !changed {
+                return None;
+            }
+        }
+        if let Some(ref path_regex) = path_regex {
+            if !filter_try!(changes_path_matching(
+                repo,
+                &commit,
+                parents,
+                &mut diffopts,
+                &excludes,
+                path_regex
+            )) {
+                return None;
+            }
+        }
+        Some(Ok((commit, paths)))
+    });
+    let revwalk: Box<dyn Iterator<Item = Result<Walked<'r>, Error>> + 'r> =
+        if opts.author_date_order {
+            // libgit2 only sorts by commit time, so buffer and re-sort. The
+            // sort is stable, keeping topological order between ties
+            let mut commits = revwalk.collect::<Result<Vec<_>, Error>>()?;
+            if reverse {
+                commits.sort_by_key(|(commit, _)| commit.author().when().seconds());
+            } else {
+                commits.sort_by_key(|(commit, _)| Reverse(commit.author().when().seconds()));
+            }
+            Box::new(commits.into_iter().map(Ok))
+        } else if opts.follow && reverse {
+            let commits = revwalk.collect::<Result<Vec<_>, Error>>()?;
+            Box::new(commits.into_iter().rev().map(Ok))
+        } else {
+            Box::new(revwalk)
+        };
+    Ok(revwalk)
+}
+
+/// The commits named by `opts.commits` then those of selected refs, or
+/// `HEAD`, skipping the revwalk.
+fn tips<'r>(repo: &'r Repository, opts: &WalkOptions) -> Result<Vec<Commit<'r>>, Error> {
+    if opts.commits.is_empty() && !opts.selects_refs() {
+        return Ok(vec![repo.head()?.peel_to_commit()?]);
+    }
+    let mut tips = opts
+        .commits
+        .iter()
+        .map(|commit| {
+            let single =
+                |revspec: &git2::Revspec| revspec.mode().contains(git2::RevparseMode::SINGLE);
+            match repo.revparse(commit) {
+                Ok(ref revspec) if !commit.starts_with('^') && single(revspec) => {
+                    revspec.from().unwrap().peel_to_commit()
+                }
+                Err(e) if !commit.starts_with('^') => Err(e),
+                _ => Err(Error::from_str(&format!(
+                    "--tips-only takes single revisions, not {}",
+                    commit
+                ))),
+            }
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    tips.extend(ref_tips(repo, opts)?);
+    Ok(tips)
+}
+
+/// The commits refs selected by `opts.all` and `opts.ref_globs` point at,
+/// each once, in order of the globs then of ref names. As in git, `refs/` is
+/// implied at the start of a glob, and `/*` at the end of one without
+/// wildcards, so `heads` matches every branch. Refs to anything but commits
+/// are skipped.
+fn ref_tips<'r>(repo: &'r Repository, opts: &WalkOptions) -> Result<Vec<Commit<'r>>, Error> {
+    let sorted = |refs: git2::References<'r>| -> Result<Vec<_>, Error> {
+        let mut refs = refs.collect::<Result<Vec<_>, Error>>()?;
+        refs.sort_by(|a, b| a.name_bytes().cmp(b.name_bytes()));
+        Ok(refs)
+    };
+    let mut refs = Vec::new();
+    if opts.all {
+        // `HEAD` too, in case it's detached, unless nothing is committed yet
+        match repo.head() {
+            Ok(head) => refs.push(head),
+            Err(ref e) if e.code() == ErrorCode::UnbornBranch => {}
+            Err(e) => return Err(e),
+        }
+        refs.extend(sorted(repo.references()?)?);
+    }
+    for glob in &opts.ref_globs {
+        let mut glob = if glob.starts_with("refs/") {
+            glob.clone()
+        } else {
+            format!("refs/{}", glob)
+        };
+        if !glob.contains(['*', '?', '[']) {
+            glob = format!("{}/*", glob.trim_end_matches('/'));
+        }
+        refs.extend(sorted(repo.references_glob(&glob)?)?);
+    }
+    let mut seen = HashSet::new();
+    let mut tips = Vec::new();
+    for reference in refs {
+        let commit = match reference.peel_to_commit() {
+            Ok(commit) => commit,
+            Err(ref e) if e.class() == ErrorClass::Object => continue,
+            Err(e) => return Err(e),
+        };
+        if seen.insert(commit.id()) {
+            tips.push(commit);
+        }
+    }
+    Ok(tips)
+}
+
+/// Sort commits walked from a shallow clone as libgit2 would have.
+fn sort_shallow(repo: &Repository, ids: Vec<Oid>, sorting: git2::Sort) -> Result<Vec<Oid>, Error> {
+    let commits = ids
+        .iter()
+        .map(|&id| repo.find_commit(id))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let mut order: Vec<usize> = (0..commits.len()).collect();
+    if sorting.contains(git2::Sort::TOPOLOGICAL) {
+        // Kahn's algorithm, yielding a commit once all its children have
+        // been, and otherwise keeping the walk's order
+        let index: HashMap<Oid, usize> = ids.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+        let mut children = vec![0; commits.len()];
+        for commit in &commits {
+            for parent in commit.parent_ids() {
+                if let Some(&i) = index.get(&parent) {
+                    children[i] += 1;
+                }
+            }
+        }
+        let mut ready: BinaryHeap<_> = (0..commits.len())
+            .filter(|&i| children[i] == 0)
+            .map(Reverse)
+            .collect();
+        order.clear();
+        while let Some(Reverse(i)) = ready.pop() {
+            order.push(i);
+            for parent in commits[i].parent_ids() {
+                if let Some(&p) = index.get(&parent) {
+                    children[p] -= 1;
+                    if children[p] == 0 {
+                        ready.push(Reverse(p));
+                    }
+                }
+            }
+        }
+    } else if sorting.contains(git2::Sort::TIME) {
+        order.sort_by_key(|&i| Reverse(commits[i].time().seconds()));
+    }
+    if sorting.contains(git2::Sort::REVERSE) {
+        order.reverse();
+    }
+    Ok(order.into_iter().map(|i| ids[i]).collect())
+}
+
+/// Sort commits topologically, and otherwise by commit time, newest first, then
+/// by id, so the order depends only on the commits and not how they were
+/// walked.
+fn sort_stable(repo: &Repository, ids: Vec<Oid>, reverse: bool) -> Result<Vec<Oid>, Error> {
+    let commits = ids
+        .iter()
+        .map(|&id| repo.find_commit(id))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let index: HashMap<Oid, usize> = ids.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+    let mut children = vec![0; commits.len()];
+    for commit in &commits {
+        for parent in commit.parent_ids() {
+            if let Some(&i) = index.get(&parent) {
+                children[i] += 1;
+            }
+        }
+    }
+    let key = |i: usize| (commits[i].time().seconds(), Reverse(ids[i]), i);
+    let mut ready: BinaryHeap<_> = (0..commits.len())
+        .filter(|&i| children[i] == 0)
+        .map(key)
+        .collect();
+    let mut order = Vec::with_capacity(commits.len());
+    while let Some((_, _, i)) = ready.pop() {
+        order.push(ids[i]);
+        for parent in commits[i].parent_ids() {
+            if let Some(&p) = index.get(&parent) {
+                children[p] -= 1;
+                if children[p] == 0 {
+                    ready.push(key(p));
+                }
+            }
+        }
+    }
+    if reverse {
+        order.reverse();
+    }
+    Ok(order)
+}
+
+/// Order each run of commits sharing a commit time by id, for a walk sorted by
+/// time. Children still come before their parents, or after with `reverse`.
+fn break_ties<'r>(
+    repo: &'r Repository,
+    mut ids: impl Iterator<Item = Result<Oid, Error>> + 'r,
+    reverse: bool,
+) -> impl Iterator<Item = Result<Oid, Error>> + 'r {
+    let mut ready = VecDeque::new();
+    let mut next: Option<Commit> = None;
+    iter::from_fn(move || {
+        if let Some(id) = ready.pop_front() {
+            return Some(Ok(id));
+        }
+        let first = match next.take() {
+            Some(commit) => commit,
+            None => match ids.next()?.and_then(|id| repo.find_commit(id)) {
+                Ok(commit) => commit,
+                Err(e) => return Some(Err(e)),
+            },
+        };
+        let time = first.time().seconds();
+        let mut run = vec![first];
+        for id in ids.by_ref() {
+            match id.and_then(|id| repo.find_commit(id)) {
+                Ok(commit) if commit.time().seconds() == time => run.push(commit),
+                Ok(commit) => {
+                    next = Some(commit);
+                    break;
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        ready.extend(order_run(&run, reverse));
+        ready.pop_front().map(Ok)
+    })
+}
+
+/// Ids of `run` in ascending order, except that a commit waits for its
+/// children in the run, or with `reverse` its parents.
+fn order_run(run: &[Commit], reverse: bool) -> Vec<Oid> {
+    if run.len() == 1 {
+        return vec![run[0].id()];
+    }
+    let index: HashMap<Oid, usize> = run.iter().enumerate().map(|(i, c)| (c.id(), i)).collect();
+    // Which commits each must come before, and how many each waits for
+    let mut releases = vec![Vec::new(); run.len()];
+    let mut waiting = vec![0; run.len()];
+    for (child, commit) in run.iter().enumerate() {
+        for parent in commit.parent_ids() {
+            if let Some(&parent) = index.get(&parent) {
+                let (before, after) = if reverse {
+                    (parent, child)
+                } else {
+                    (child, parent)
+                };
+                releases[before].push(after);
+                waiting[after] += 1;
+            }
+        }
+    }
+    let mut heap: BinaryHeap<_> = (0..run.len())
+        .filter(|&i| waiting[i] == 0)
+        .map(|i| Reverse((run[i].id(), i)))
+        .collect();
+    let mut order = Vec::with_capacity(run.len());
+    while let Some(Reverse((id, i))) = heap.pop() {
+        order.push(id);
+        for &after in &releases[i] {
+            waiting[after] -= 1;
+            if waiting[after] == 0 {
+                heap.push(Reverse((run[after].id(), after)));
+            }
+        }
+    }
+    order
+}
+
+/// Commits handed to each worker thread per batch when diffing in parallel.
+const BATCH_PER_JOB: usize = 64;
+
+/// What commits are diffed against, rather than their parents.
+#[derive(Clone, Copy, Debug)]
+enum Base {
+    /// The tree of `--base`.
+    Tree(Oid),
+    /// An empty tree, so every file counts as added.
+    Empty,
+}
+
+/// Similarity percentage renames are detected at when following, unless
+/// `find_renames` says otherwise.
+const FOLLOW_RENAME_THRESHOLD: u16 = 50;
+
+/// Diff a batch of commits across `opts.jobs` threads, preserving order.
+///
+/// `Repository` can't be shared between threads, so each worker opens its own.
+fn records_parallel(
+    path: &Path,
+    ids: Vec<Result<(Oid, Paths), Error>>,
+    grafts: &Grafts,
+    base: Option<Base>,
+    opts: &WalkOptions,
+    globs: Option<&Globs>,
+) -> Vec<Result<Record, RecordError>> {
+    let chunk_size = ids.len().div_ceil(opts.jobs);
+    let mut chunks = Vec::new();
+    let mut ids = ids.into_iter().peekable();
+    while ids.peek().is_some() {
+        chunks.push(ids.by_ref().take(chunk_size).collect::<Vec<_>>());
+    }
+    thread::scope(|scope| {
+        let workers: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| {
+                scope.spawn(move || {
+                    let repo = Repository::open(path).and_then(|repo| {
+                        if let Some(ref dir) = opts.object_dir {
+                            repo.odb()?.add_disk_alternate(dir)?;
+                        }
+                        Ok(repo)
+                    });
+                    let mut diffopts = diff_options(opts);
+                    chunk
+                        .into_iter()
+                        .map(|id| {
+                            let repo = repo.as_ref().map_err(|e| Error::from_str(e.message()))?;
+                            let (id, paths) = id?;
+                            let commit = repo.find_commit(id)?;
+                            match paths {
+                                Some(paths) => {
+                                    let mut diffopts = diff_options_for(opts, &paths);
+                                    record(repo, &commit, grafts, base, opts, &mut diffopts, globs)
+                                }
+                                None => {
+                                    record(repo, &commit, grafts, base, opts, &mut diffopts, globs)
+                                }
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("diff worker panicked"))
+            .collect()
+    })
+}
+
+/// Sort records by `key`, largest or newest first, keeping ties in order.
+///
+/// Errors are kept rather than ending the sort, and come first, so commits
+/// that couldn't be diffed are reported before any output.
+fn sort_records(
+    repo: &Repository,
+    records: Vec<Result<Record, RecordError>>,
+    key: SortOutput,
+) -> Vec<Result<Record, RecordError>> {
+    let mut failed = Vec::new();
+    let mut keyed = Vec::new();
+    for record in records {
+        let keyed_record = record.and_then(|record| {
+            let commit = || {
+                repo.find_commit(record.oid).map_err(|error| RecordError {
+                    commit: Some(record.oid),
+                    error,
+                })
+            };
+            let key = match key {
+                SortOutput::CommitDate => commit()?.time().seconds(),
+                SortOutput::AuthorDate => commit()?.author().when().seconds(),
+                SortOutput::Insertions => record.short_stat.insertions as i64,
+                SortOutput::Deletions => record.short_stat.deletions as i64,
+                SortOutput::Churn => record.short_stat.churn() as i64,
+                SortOutput::Files => record.short_stat.files_changed as i64,
+            };
+            Ok((key, record))
+        });
+        match keyed_record {
+            Ok(keyed_record) => keyed.push(keyed_record),
+            Err(e) => failed.push(Err(e)),
+        }
+    }
+    keyed.sort_by_key(|&(key, _)| Reverse(key));
+    failed
+        .into_iter()
+        .chain(keyed.into_iter().map(|(_, record)| Ok(record)))
+        .collect()
+}
+
+/// Short names of the refs pointing at each commit, with `HEAD` first and the
+/// rest sorted. Tags are peeled to the commit they point at.
+fn decorations(repo: &Repository) -> Result<HashMap<Oid, Vec<String>>, Error> {
+    let mut decorations: HashMap<Oid, Vec<String>> = HashMap::new();
+    for reference in repo.references()? {
+        let reference = reference?;
+        if let Ok(commit) = reference.peel_to_commit() {
+            let name = lossy(reference.shorthand_bytes());
+            decorations.entry(commit.id()).or_default().push(name);
+        }
+    }
+    for refs in decorations.values_mut() {
+        refs.sort();
+    }
+    if let Ok(head) = repo.head().and_then(|head| head.peel_to_commit()) {
+        decorations
+            .entry(head.id())
+            .or_default()
+            .insert(0, "HEAD".to_owned());
+    }
+    Ok(decorations)
+}
+
+/// Diff options applied to every diff, matching the walk's pathspecs unless
+/// they're glob pathspecs, which libgit2 can't match.
+fn diff_options(opts: &WalkOptions) -> DiffOptions {
+    diff_options_for(opts, &opts.included_specs())
+}
+
+/// Diff options as for the walk, but matching `specs` instead. Followed paths
+/// are always matched literally.
+fn diff_options_for<S: AsRef<str>>(opts: &WalkOptions, specs: &[S]) -> DiffOptions {
+    let mut diffopts = DiffOptions::new();
+    if !opts.pathspec_glob || opts.follow {
+        for spec in specs {
+            diffopts.pathspec(spec.as_ref());
+        }
+    }
+    diffopts
+        .disable_pathspec_match(opts.literal_pathspecs || opts.follow)
+        .ignore_case(opts.pathspec_icase)
+        .ignore_whitespace(opts.ignore_whitespace)
+        .ignore_whitespace_change(opts.ignore_whitespace_change)
+        .ignore_whitespace_eol(opts.ignore_whitespace_eol)
+        .minimal(opts.diff_algorithm == DiffAlgorithm::Minimal)
+        .patience(opts.diff_algorithm == DiffAlgorithm::Patience)
+        .ignore_submodules(opts.ignore_submodules);
+    if let Some(lines) = opts.context_lines {
+        diffopts.context_lines(lines);
+    }
+    if let Some(lines) = opts.interhunk_lines {
+        diffopts.interhunk_lines(lines);
+    }
+    diffopts
+}
+
+/// The walk's pathspecs as globs, if they're to be matched after diffing.
+fn globs(opts: &WalkOptions) -> Result<Option<Globs>, Error> {
+    let specs: Vec<_> = opts
+        .included_specs()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    if opts.pathspec_glob && !opts.follow && !specs.is_empty() {
+        Ok(Some(Globs::new(&specs, opts.pathspec_icase)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Whether a diff changes any path, on either side, matching `globs` if
+/// given and not left out by `excludes`.
+fn touches(diff: &Diff, globs: Option<&Globs>, excludes: &Excludes) -> bool {
+    diff.deltas().any(|delta| {
+        [delta.old_file(), delta.new_file()]
+            .iter()
+            .filter_map(|file| file.path_bytes())
+            .map(lossy)
+            .any(|path| {
+                let matched = match globs {
+                    Some(globs) => globs.is_match(&path),
+                    None => true,
+                };
+                matched && !excludes.excludes(&path)
+            })
+    })
+}
+
+/// Pathspec flags matching how `diff_options` treats the walk's pathspecs.
+fn pathspec_flags(opts: &WalkOptions) -> PathspecFlags {
+    let mut flags = PathspecFlags::DEFAULT;
+    if opts.literal_pathspecs {
+        flags |= PathspecFlags::NO_GLOB;
+    }
+    if opts.pathspec_icase {
+        flags |= PathspecFlags::IGNORE_CASE;
+    }
+    flags
+}
+
+/// Diff a commit and build its record, naming the commit in any error.
+fn record(
+    repo: &Repository,
+    commit: &Commit,
+    grafts: &Grafts,
+    base: Option<Base>,
+    opts: &WalkOptions,
+    diffopts: &mut DiffOptions,
+    globs: Option<&Globs>,
+) -> Result<Record, RecordError> {
+    diff_record(repo, commit, grafts, base, opts, diffopts, globs).map_err(|error| RecordError {
+        commit: Some(commit.id()),
+        error,
+    })
+}
+
+/// Diff `commit` against `base` if given, otherwise its parents. A commit at
+/// a shallow boundary is diffed as a root commit.
+fn diff_record(
+    repo: &Repository,
+    commit: &Commit,
+    grafts: &Grafts,
+    base: Option<Base>,
+    opts: &WalkOptions,
+    diffopts: &mut DiffOptions,
+    globs: Option<&Globs>,
+) -> Result<Record, Error> {
+    let parents = grafts.parent_count(commit);
+    // Against a base, merges are diffed like any other commit
+    let merge_diff = match base {
+        Some(_) => MergeDiff::FirstParent,
+        None => opts.merge_diff_for(parents),
+    };
+    let a = match (base, parents, merge_diff) {
+        (Some(Base::Tree(tree)), _, _) => Some(repo.find_tree(tree)?),
+        (Some(Base::Empty), _, _) | (None, 0, _) => None,
+        (None, _, MergeDiff::SecondParent) => Some(commit.parent(1)?.tree()?),
+        _ => Some(commit.parent(0)?.tree()?),
+    };
+    let b = commit.tree()?;
+    let mut diff = repo.diff_tree_to_tree(a.as_ref(), Some(&b), Some(diffopts))?;
+    if let Some(threshold) = opts.rename_threshold() {
+        let mut findopts = DiffFindOptions::new();
+        findopts.renames(true).rename_threshold(threshold);
+        diff.find_similar(Some(&mut findopts))?;
+    }
+    let excludes = Excludes::new(opts)?;
+    let generated = Excludes::globs(&opts.ignore_generated)?;
+    let filtered = globs.is_some() || !excludes.is_empty() || opts.filters_files();
+    let (short_stat, files) = if merge_diff == MergeDiff::Combined || filtered {
+        // Glob pathspecs, exclusions, generated files, the diff filter and
+        // submodules, which libgit2 can't apply to the diff, are matched
+        // against each file once diffed
+        let mut files = files::file_stats(&diff)?;
+        files::mark_submodules(&mut files, a.as_ref(), &b);
+        files.retain(|file| opts.keeps_file(file, globs, &excludes, &generated));
+        // Only files that differ from every parent appear in a combined diff
+        let others = if merge_diff == MergeDiff::Combined {
+            parents - 1
+        } else {
+            0
+        };
+        for parent in commit.parents().skip(1).take(others) {
+            let diff = repo.diff_tree_to_tree(Some(&parent.tree()?), Some(&b), Some(diffopts))?;
+            let changed: HashSet<_> = diff
+                .deltas()
+                .filter_map(|delta| delta.new_file().path_bytes().map(lossy))
+                .collect();
+            files.retain(|file| changed.contains(&file.path));
+        }
+        (ShortStat::from_files(&files), files)
+    } else if opts.needs_files() {
+        (diff.stats()?.into(), files::file_stats(&diff)?)
+    } else {
+        (diff.stats()?.into(), Vec::new())
+    };
+    let author = commit.author();
+    let encoding = encoding_of(commit, opts);
+    let net = if opts.with_net {
+        Some(short_stat.insertions as i64 - short_stat.deletions as i64)
+    } else {
+        None
+    };
+    let ratio = |n: usize| {
+        let changed = short_stat.churn();
+        if changed == 0 {
+            None
+        } else {
+            Some(n as f64 / changed as f64)
+        }
+    };
+    let (insertion_ratio, deletion_ratio) = if opts.with_ratios {
+        (
+            Some(ratio(short_stat.insertions)),
+            Some(ratio(short_stat.deletions)),
+        )
+    } else {
+        (None, None)
+    };
+    let bytes = if opts.with_bytes {
+        // Only count the files counted in the stats, if some were left out
+        let filtered = merge_diff == MergeDiff::Combined || filtered;
+        let paths: HashSet<_> = files.iter().map(|file| &file.path[..]).collect();
+        Some(byte_delta(repo, &diff, |path| {
+            !filtered || paths.contains(path)
+        })?)
+    } else {
+        None
+    };
+    Ok(Record {
+        net,
+        churn: if opts.with_churn {
+            Some(short_stat.churn())
+        } else {
+            None
+        },
+        bytes,
+        insertion_ratio,
+        deletion_ratio,
+        short_stat,
+        oid: commit.id(),
+        merge_diff: if merge_diff == MergeDiff::Combined {
+            Some(merge_diff)
+        } else {
+            None
+        },
+        binary_files: if opts.with_binary_count {
+            Some(files.iter().filter(|file| file.binary).count())
+        } else {
+            None
+        },
+        dirs: if opts.with_dirs {
+            let dirs: HashSet<_> = files
+                .iter()
+                .map(|file| Path::new(&file.path).parent())
+                .collect();
+            Some(dirs.len())
+        } else {
+            None
+        },
+        files,
+        parents: if opts.with_parents {
+            Some(
+                grafts
+                    .parent_ids(commit)
+                    .into_iter()
+                    .map(|id| short_id(repo, id, opts.abbrev))
+                    .collect::<Result<_, _>>()?,
+            )
+        } else {
+            None
+        },
+        tree: if opts.with_tree {
+            Some(b.id().to_string())
+        } else {
+            None
+        },
+        root: if opts.with_root_flag {
+            Some(parents == 0)
+        } else {
+            None
+        },
+        refs: None,
+        subject: if opts.with_subject {
+            Some(subject(commit, opts.subject_maxlen, encoding))
+        } else {
+            None
+        },
+        number: None,
+        cumulative_insertions: None,
+        cumulative_deletions: None,
+        insertions_average: None,
+        deletions_average: None,
+        id: if opts.with_id {
+            Some(short_id(repo, commit.id(), opts.abbrev)?)
+        } else {
+            None
+        },
+        author_name: if opts.with_author {
+            Some(encoding.decode(author.name_bytes()))
+        } else {
+            None
+        },
+        author_email: if opts.with_author {
+            Some(encoding.decode(author.email_bytes()))
+        } else {
+            None
+        },
+        time: if opts.with_time {
+            let when = author.when();
+            Some(Time::new(
+                when.seconds(),
+                when.offset_minutes(),
+                opts.date_format,
+            ))
+        } else {
+            None
+        },
+        offset_minutes: if opts.with_time {
+            Some(author.when().offset_minutes())
+        } else {
+            None
+        },
+        delta_time: if opts.with_delta_time {
+            Some(match parents {
+                0 => None,
+                _ => Some(commit.time().seconds() - commit.parent(0)?.time().seconds()),
+            })
+        } else {
+            None
+        },
+    })
+}
+
+/// Bytes added less bytes removed across the blobs `diff` changes, for the
+/// paths `counted` accepts.
+fn byte_delta<F: Fn(&str) -> bool>(
+    repo: &Repository,
+    diff: &Diff,
+    counted: F,
+) -> Result<i64, Error> {
+    let size = |id: Oid| -> Result<i64, Error> {
+        if id.is_zero() {
+            return Ok(0);
+        }
+        match repo.find_blob(id) {
+            Ok(blob) => Ok(blob.size() as i64),
+            // Submodules are commits in another repository
+            Err(ref e) if e.code() == ErrorCode::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    };
+    let mut bytes = 0;
+    for delta in diff.deltas() {
+        let path = delta
+            .new_file()
+            .path_bytes()
+            .or_else(|| delta.old_file().path_bytes())
+            .map(lossy)
+            .unwrap_or_default();
+        if counted(&path) {
+            bytes += size(delta.new_file().id())? - size(delta.old_file().id())?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Those of `ids` descended from any of `bottoms`, following only first
+/// parents if `first_parent`, so on an ancestry path from one.
+fn descendants(
+    repo: &Repository,
+    ids: &[Oid],
+    bottoms: &[Oid],
+    first_parent: bool,
+) -> Result<HashSet<Oid>, Error> {
+    let mut children: HashMap<Oid, Vec<Oid>> = HashMap::new();
+    for &id in ids {
+        let commit = repo.find_commit(id)?;
+        let parents = commit
+            .parent_ids()
+            .take(if first_parent { 1 } else { usize::MAX });
+        for parent in parents {
+            children.entry(parent).or_default().push(id);
+        }
+    }
+    let mut descendants = HashSet::new();
+    let mut queue: Vec<Oid> = bottoms.to_vec();
+    while let Some(id) = queue.pop() {
+        for &child in children.get(&id).into_iter().flatten() {
+            if descendants.insert(child) {
+                queue.push(child);
+            }
+        }
+    }
+    Ok(descendants)
+}
+
+/// Fail if some pathspec matches no file in the trees of any of `ids`.
+fn check_pathspecs_match(repo: &Repository, ids: &[Oid], opts: &WalkOptions) -> Result<(), Error> {
+    let flags = pathspec_flags(opts) | PathspecFlags::FIND_FAILURES | PathspecFlags::FAILURES_ONLY;
+    let mut unmatched: Vec<String> = opts
+        .included_specs()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    for id in ids {
+        let tree = repo.find_commit(*id)?.tree()?;
+        let pathspec = Pathspec::new(&unmatched)?;
+        let matches = pathspec.match_tree(&tree, flags)?;
+        unmatched = matches.failed_entries().map(lossy).collect();
+        if unmatched.is_empty() {
+            return Ok(());
+        }
+    }
+    Err(Error::from_str(&format!(
+        "pathspec '{}' did not match any files in the walked history",
+        unmatched[0]
+    )))
+}
+
+/// Whether a commit is in a sample of `rate` of all commits. Ids are
+/// uniformly distributed, so their leading bytes are used as the draw.
+fn sampled(id: Oid, rate: f64) -> bool {
+    let mut draw = [0; 8];
+    draw.copy_from_slice(&id.as_bytes()[..8]);
+    rate >= 1.0 || (u64::from_be_bytes(draw) as f64) < rate * 2f64.powi(64)
+}
+
+/// Id of the tree entry at `path` in `commit`'s tree, if there is one.
+fn entry_id(commit: &Commit, path: &Path) -> Result<Option<Oid>, Error> {
+    match commit.tree()?.get_path(path) {
+        Ok(entry) => Ok(Some(entry.id())),
+        Err(ref e) if e.code() == ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn match_with_parent(
+    repo: &Repository,
+    commit: &Commit,
+    parent: &Commit,
+    opts: &mut DiffOptions,
+    globs: Option<&Globs>,
+    excludes: &Excludes,
+) -> Result<bool, Error> {
+    let a = parent.tree()?;
+    let b = commit.tree()?;
+    let diff = repo.diff_tree_to_tree(Some(&a), Some(&b), Some(opts))?;
+    Ok(touches(&diff, globs, excludes))
+}
+
+/// The hex of `id`, abbreviated to at least `abbrev` digits if given.
+///
+/// Like git, the id is lengthened until no other object shares the prefix.
+pub fn short_id(repo: &Repository, id: Oid, abbrev: Option<usize>) -> Result<String, Error> {
+    let hex = id.to_string();
+    let mut len = match abbrev {
+        Some(abbrev) => abbrev.clamp(MIN_ABBREV, hex.len()),
+        None => return Ok(hex),
+    };
+    while len < hex.len() {
+        match repo.revparse_single(&hex[..len]) {
+            Err(ref e) if e.code() == ErrorCode::Ambiguous => len += 1,
+            _ => break,
+        }
+    }
+    Ok(hex[..len].to_owned())
+}
+
+/// Shortest abbreviation of an id, as git allows.
+const MIN_ABBREV: usize = 4;
+
+/// The encoding to decode a commit's message and signatures from.
+fn encoding_of(commit: &Commit, opts: &WalkOptions) -> Encoding {
+    opts.encoding
+        .or_else(|| commit.message_encoding().and_then(Encoding::for_label))
+        .unwrap_or(Encoding::Utf8)
+}
+
+/// The first line of a commit's message, without trailing whitespace and cut
+/// to at most `maxlen` characters.
+fn subject(commit: &Commit, maxlen: Option<usize>, encoding: Encoding) -> String {
+    let subject = encoding.decode(commit.summary_bytes().unwrap_or_default());
+    let subject = subject.trim_end();
+    match maxlen {
+        Some(maxlen) => subject.chars().take(maxlen).collect(),
+        None => subject.to_owned(),
+    }
+}
+
+/// Whether any path changed by a commit since its first parent matches `regex`.
+fn changes_path_matching(
+    repo: &Repository,
+    commit: &Commit,
+    parents: usize,
+    opts: &mut DiffOptions,
+    excludes: &Excludes,
+    regex: &Regex,
+) -> Result<bool, Error> {
+    let a = match parents {
+        0 => None,
+        _ => Some(commit.parent(0)?.tree()?),
+    };
+    let diff = repo.diff_tree_to_tree(a.as_ref(), Some(&commit.tree()?), Some(opts))?;
+    let matched = diff.deltas().any(|delta| {
+        [delta.old_file().path_bytes(), delta.new_file().path_bytes()]
+            .iter()
+            .flatten()
+            .map(|path| lossy(path))
+            .any(|path| !excludes.excludes(&path) && regex.is_match(&path))
+    });
+    Ok(matched)
+}
+
+/// The name and email of a signature, decoded from `encoding` and
+/// canonicalized by `mailmap` if given.
+fn identity(
+    signature: &Signature,
+    encoding: Encoding,
+    mailmap: Option<&Mailmap>,
+) -> (String, String) {
+    let name = encoding.decode(signature.name_bytes());
+    let email = encoding.decode(signature.email_bytes());
+    match mailmap {
+        Some(mailmap) => mailmap.resolve(&name, &email),
+        None => (name, email),
+    }
+}
+
+/// Whether an identity's name or email contains `pattern`, ignoring case.
+fn signature_matches((name, email): &(String, String), pattern: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    name.to_lowercase().contains(&pattern) || email.to_lowercase().contains(&pattern)
+}
+
+/// An identity written as git shows it, `Name <email>`.
+fn written((name, email): &(String, String)) -> String {
+    format!("{} <{}>", name, email)
+}
+
+/// Decode possibly non-UTF-8 bytes from git, replacing invalid sequences.
+fn lossy(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
@@ -0,0 +1,132 @@
+//! Author identities canonicalized by a `.mailmap`, as `git log --use-mailmap`
+//! does. git2 has no bindings for libgit2's mailmap, so it's parsed by hand.
+//!
+//! Each line maps a commit's email, and optionally its name, to a proper name,
+//! a proper email or both:
+//!
+//! ```text
+//! Proper Name <commit@email>
+//! <proper@email> <commit@email>
+//! Proper Name <proper@email> <commit@email>
+//! Proper Name <proper@email> Commit Name <commit@email>
+//! ```
+
+use git2::{Error, ErrorCode, Repository};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// The proper name and email to replace a commit's with, either of which may
+/// be left as is.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Proper {
+    name: Option<String>,
+    email: Option<String>,
+}
+
+/// Mappings from commit identities to proper ones.
+#[derive(Clone, Debug, Default)]
+pub struct Mailmap {
+    /// Keyed by lowercased commit email, and lowercased commit name for
+    /// entries which only apply to that name.
+    entries: HashMap<(String, Option<String>), Proper>,
+}
+
+impl Mailmap {
+    /// Read the repository's mailmap as git does: `.mailmap` at the top of the
+    /// working tree, or in `HEAD` for a bare repository, then any file named
+    /// by `mailmap.file`. Later entries override earlier ones.
+    pub fn load(repo: &Repository) -> Result<Self, Error> {
+        let mut mailmap = Self::default();
+        match repo.workdir() {
+            Some(workdir) => mailmap.read_file(&workdir.join(".mailmap"))?,
+            None => match repo.revparse_single("HEAD:.mailmap") {
+                Ok(object) => {
+                    let blob = object.peel_to_blob()?;
+                    mailmap.parse(&String::from_utf8_lossy(blob.content()));
+                }
+                Err(ref e) if e.code() == ErrorCode::NotFound => {}
+                Err(e) => return Err(e),
+            },
+        }
+        match repo.config()?.get_path("mailmap.file") {
+            Ok(path) => mailmap.read_file(&path)?,
+            Err(ref e) if e.code() == ErrorCode::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(mailmap)
+    }
+
+    /// Add the entries of the file at `path`, if there is one.
+    fn read_file(&mut self, path: &Path) -> Result<(), Error> {
+        if !path.exists() {
+            return Ok(());
+        }
+        let text = fs::read(path)
+            .map_err(|e| Error::from_str(&format!("couldn't read {}: {}", path.display(), e)))?;
+        self.parse(&String::from_utf8_lossy(&text));
+        Ok(())
+    }
+
+    /// Add the entries of a mailmap, skipping comments and malformed lines.
+    pub fn parse(&mut self, text: &str) {
+        for line in text.lines() {
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+            let mut pairs = Vec::new();
+            let mut rest = line;
+            while pairs.len() < 2 {
+                let open = match rest.find('<') {
+                    Some(open) => open,
+                    None => break,
+                };
+                let close = match rest[open..].find('>') {
+                    Some(close) => open + close,
+                    None => break,
+                };
+                let name = rest[..open].trim();
+                let name = if name.is_empty() {
+                    None
+                } else {
+                    Some(name.to_owned())
+                };
+                pairs.push((name, rest[open + 1..close].trim().to_owned()));
+                rest = &rest[close + 1..];
+            }
+            let mut pairs = pairs.into_iter();
+            let (commit_email, commit_name, proper) = match (pairs.next(), pairs.next()) {
+                (Some((name, email)), None) if name.is_some() => {
+                    (email, None, Proper { name, email: None })
+                }
+                (Some((name, email)), Some((commit_name, commit_email))) => {
+                    let email = if email.is_empty() { None } else { Some(email) };
+                    (commit_email, commit_name, Proper { name, email })
+                }
+                _ => continue,
+            };
+            let key = (
+                commit_email.to_lowercase(),
+                commit_name.map(|name| name.to_lowercase()),
+            );
+            self.entries.insert(key, proper);
+        }
+    }
+
+    /// The proper name and email for a commit's, matching case insensitively
+    /// and preferring entries for both name and email over those for email.
+    pub fn resolve(&self, name: &str, email: &str) -> (String, String) {
+        let email_key = email.to_lowercase();
+        let proper = self
+            .entries
+            .get(&(email_key.clone(), Some(name.to_lowercase())))
+            .or_else(|| self.entries.get(&(email_key, None)));
+        match proper {
+            Some(proper) => (
+                proper.name.clone().unwrap_or_else(|| name.to_owned()),
+                proper.email.clone().unwrap_or_else(|| email.to_owned()),
+            ),
+            None => (name.to_owned(), email.to_owned()),
+        }
+    }
+}
@@ -1,44 +1,79 @@
-//! Dump short stats from git log for a repo.
-//!
-//! Based on: https://github.com/rust-lang/git2-rs/blob/43b8e28ada54120ff55dc550ab177109f162eb10/examples/log.rs#L1
+//! Command line interface for dumping short stats from git log for a repo.
 
 #![deny(warnings)]
 use docopt::Docopt;
-use git2::{Commit, DiffOptions, ObjectType, Repository};
-use git2::{DiffStats, Error, Pathspec};
+use git2::{Error, ErrorClass, ErrorCode, Repository, RepositoryOpenFlags};
 use serde_derive::{Deserialize, Serialize};
-use std::str;
-
-#[derive(Clone, Serialize, Debug, PartialEq)]
-pub struct ShortStat {
-    #[serde(rename(serialize = "f"))]
-    pub files_changed: usize,
-    #[serde(rename(serialize = "i"))]
-    pub insertions: usize,
-    #[serde(rename(serialize = "d"))]
-    pub deletions: usize,
-}
-
-impl From<DiffStats> for ShortStat {
-    fn from(diff_stats: DiffStats) -> Self {
-        Self {
-            files_changed: diff_stats.files_changed(),
-            insertions: diff_stats.insertions(),
-            deletions: diff_stats.deletions(),
-        }
-    }
-}
+use shortstat_dump::aggregate::{ByAuthor, ByExtension, ByPeriod};
+use shortstat_dump::date::{self, DateFormat, Period};
+use shortstat_dump::files::FileRecord;
+use shortstat_dump::output::{Counter, Entry, Format, Printer, Schema, Template, Truncated};
+use shortstat_dump::{
+    commits, short_id, shortstats, validate, worktree_shortstat, Summary, WalkOptions,
+};
+use std::cell::Cell;
+use std::env;
+use std::error;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::mem;
+use std::path::Path;
+use std::process;
+use std::rc::Rc;
+use std::str::FromStr;
 
 #[derive(Deserialize)]
 struct Args {
+    arg_arg: Vec<String>,
+    /// Revisions, the arguments before any `--`.
+    #[serde(skip)]
     arg_commit: Vec<String>,
+    /// Pathspecs, the arguments after `--`.
+    #[serde(skip)]
     arg_spec: Vec<String>,
+    flag_revs_from: Option<String>,
+    flag_merge_base: Option<String>,
+    flag_tips_only: bool,
+    flag_base: Option<String>,
+    flag_snapshot: bool,
+    flag_literal_pathspecs: bool,
+    flag_pathspec_icase: bool,
+    flag_pathspec_glob: bool,
+    flag_strict_pathspec: bool,
+    flag_follow: bool,
     flag_topo_order: bool,
+    flag_stable: bool,
     flag_date_order: bool,
+    flag_committer_date_order: bool,
+    flag_author_date_order: bool,
     flag_reverse: bool,
+    flag_first_parent: bool,
+    flag_allow_shallow: bool,
     flag_git_dir: Option<String>,
+    flag_work_tree: Option<String>,
+    flag_object_dir: Option<String>,
     flag_skip: Option<usize>,
     flag_max_count: Option<usize>,
+    flag_min_insertions: Option<usize>,
+    flag_max_insertions: Option<usize>,
+    flag_min_deletions: Option<usize>,
+    flag_max_deletions: Option<usize>,
+    flag_min_churn: Option<usize>,
+    flag_max_churn: Option<usize>,
+    flag_skip_empty: bool,
+    flag_binary_only: bool,
+    flag_ignore_generated: Vec<String>,
+    flag_glob: Vec<String>,
+    flag_all: bool,
+    flag_ancestry_path: bool,
+    flag_branches: bool,
+    flag_tags: bool,
+    flag_ignore_submodules: bool,
+    flag_submodules_only: bool,
+    flag_diff_filter: Option<String>,
+    flag_sort_output: Option<String>,
     flag_merges: bool,
     flag_no_merges: bool,
     flag_no_min_parents: bool,
@@ -46,139 +81,515 @@ struct Args {
     flag_max_parents: Option<usize>,
     flag_min_parents: Option<usize>,
     flag_patch: bool,
+    flag_with_id: bool,
+    flag_abbrev: Option<usize>,
+    flag_with_author: bool,
+    flag_use_mailmap: bool,
+    flag_with_time: bool,
+    flag_date_format: String,
+    flag_with_delta_time: bool,
+    flag_with_binary_count: bool,
+    flag_with_dirs: bool,
+    flag_verify_net: bool,
+    flag_with_churn: bool,
+    flag_with_ratios: bool,
+    flag_objects: bool,
+    flag_with_parents: bool,
+    flag_with_tree: bool,
+    flag_with_root_flag: bool,
+    flag_decorate: bool,
+    flag_with_subject: bool,
+    flag_subject_maxlen: Option<usize>,
+    flag_encoding: Option<String>,
+    flag_number: bool,
+    flag_cumulative: bool,
+    flag_window: Option<usize>,
+    flag_author: Option<String>,
+    flag_committer: Option<String>,
+    flag_author_regex: Option<String>,
+    flag_committer_regex: Option<String>,
+    flag_grep: Option<String>,
+    flag_invert_grep: bool,
+    flag_path_regex: Option<String>,
+    flag_changed_path: Option<String>,
+    flag_sample: Option<f64>,
+    flag_since: Option<String>,
+    flag_until: Option<String>,
+    flag_summary: bool,
+    flag_header: bool,
+    flag_progress: bool,
+    flag_progress_always: bool,
+    flag_format: String,
+    flag_error_format: String,
+    flag_template: Option<String>,
+    flag_jobs: Option<usize>,
+    flag_per_file: bool,
+    flag_with_hunks: bool,
+    flag_json_array: bool,
+    flag_null: bool,
+    flag_output: Option<String>,
+    flag_count: bool,
+    flag_keep_going: bool,
+    flag_limit_commits: Option<usize>,
+    flag_limit_bytes: Option<usize>,
+    flag_validate: bool,
+    flag_worktree: bool,
+    flag_by_author: bool,
+    flag_by_extension: bool,
+    flag_group_by: Option<String>,
+    flag_ignore_whitespace: bool,
+    flag_ignore_whitespace_change: bool,
+    flag_ignore_whitespace_eol: bool,
+    flag_diff_algorithm: String,
+    flag_context: Option<u32>,
+    flag_inter_hunk_context: Option<u32>,
+    flag_merge_diff: String,
+    flag_find_renames: bool,
+    flag_no_renames: bool,
+    flag_rename_threshold: String,
 }
 
-fn run(args: &Args) -> Result<(), Error> {
-    let path = args.flag_git_dir.as_ref().map(|s| &s[..]).unwrap_or(".");
-    let repo = Repository::open(path)?;
-    let mut revwalk = repo.revwalk()?;
+/// Commits processed between each update of `--progress`.
+const PROGRESS_INTERVAL: usize = 100;
 
-    // Prepare the revwalk based on CLI parameters
-    let base = if args.flag_reverse {
-        git2::Sort::REVERSE
-    } else {
-        git2::Sort::NONE
-    };
-    revwalk.set_sorting(
-        base | if args.flag_topo_order {
-            git2::Sort::TOPOLOGICAL
-        } else if args.flag_date_order {
-            git2::Sort::TIME
-        } else {
-            git2::Sort::NONE
-        },
-    );
-    for commit in &args.arg_commit {
-        if commit.starts_with('^') {
-            let obj = repo.revparse_single(&commit[1..])?;
-            revwalk.hide(obj.id())?;
-            continue;
-        }
-        let revspec = repo.revparse(commit)?;
-        if revspec.mode().contains(git2::RevparseMode::SINGLE) {
-            revwalk.push(revspec.from().unwrap().id())?;
-        } else {
-            let from = revspec.from().unwrap().id();
-            let to = revspec.to().unwrap().id();
-            revwalk.push(to)?;
-            if revspec.mode().contains(git2::RevparseMode::MERGE_BASE) {
-                let base = repo.merge_base(from, to)?;
-                let o = repo.find_object(base, Some(ObjectType::Commit))?;
-                revwalk.push(o.id())?;
+/// How a failed run is reported on stderr.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ErrorFormat {
+    /// An `error: ...` line.
+    Human,
+    /// A JSON object with the message, and libgit2's code and class for it.
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(Error::from_str(&format!("unknown error format: {}", s))),
+        }
+    }
+}
+
+/// An error as reported by `--error-format json`.
+#[derive(Serialize)]
+struct ErrorRecord {
+    error: String,
+    code: String,
+    class: String,
+}
+
+impl ErrorFormat {
+    fn report(self, e: &(dyn error::Error + 'static)) {
+        match self {
+            ErrorFormat::Human => eprintln!("error: {}", e),
+            ErrorFormat::Json => {
+                // Errors that aren't libgit2's are reported as generic ones
+                let git = git_error(e);
+                let record = ErrorRecord {
+                    error: match e.downcast_ref::<Error>() {
+                        Some(e) => e.message().to_owned(),
+                        None => e.to_string(),
+                    },
+                    code: format!("{:?}", git.map_or(ErrorCode::GenericError, Error::code)),
+                    class: format!("{:?}", git.map_or(ErrorClass::None, Error::class)),
+                };
+                eprintln!(
+                    "{}",
+                    serde_json::to_string(&record).expect("error records always serialize")
+                );
             }
-            revwalk.hide(from)?;
         }
     }
-    if args.arg_commit.is_empty() {
-        revwalk.push_head()?;
+}
+
+/// The libgit2 error behind `e`, if there is one.
+fn git_error<'e>(e: &'e (dyn error::Error + 'static)) -> Option<&'e Error> {
+    let mut next = Some(e);
+    while let Some(e) = next {
+        if let Some(e) = e.downcast_ref::<Error>() {
+            return Some(e);
+        }
+        next = e.source();
     }
+    None
+}
 
-    // Prepare our diff options and pathspec matcher
-    let (mut diffopts, mut diffopts2) = (DiffOptions::new(), DiffOptions::new());
-    for spec in &args.arg_spec {
-        diffopts.pathspec(spec);
-        diffopts2.pathspec(spec);
+/// Whether `e` is from writing to a pipe whose reader has gone away.
+fn broken_pipe(e: &(dyn error::Error + 'static)) -> bool {
+    let mut next = Some(e);
+    while let Some(e) = next {
+        if let Some(e) = e.downcast_ref::<io::Error>() {
+            return e.kind() == io::ErrorKind::BrokenPipe;
+        }
+        next = e.source();
     }
-    let ps = Pathspec::new(args.arg_spec.iter())?;
+    false
+}
 
-    // Filter our revwalk based on the CLI parameters
-    macro_rules! filter_try {
-        ($e:expr) => {
-            match $e {
-                Ok(t) => t,
-                Err(e) => return Some(Err(e)),
-            }
-        };
+/// A libgit2 error reworded for the command line, keeping the original.
+#[derive(Debug)]
+struct Reworded {
+    message: String,
+    source: Error,
+}
+
+impl Reworded {
+    fn boxed(source: Error, message: String) -> Box<dyn error::Error> {
+        Box::new(Self { message, source })
+    }
+}
+
+impl fmt::Display for Reworded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl error::Error for Reworded {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.source)
     }
-    let revwalk = revwalk
-        .filter_map(|id| {
-            let id = filter_try!(id);
-            let commit = filter_try!(repo.find_commit(id));
-            let parents = commit.parents().len();
-            if parents < args.min_parents() {
-                return None;
+}
+
+fn run(args: &Args, error_format: ErrorFormat) -> Result<(), Box<dyn error::Error>> {
+    if args.flag_patch {
+        eprintln!("warning: --patch is deprecated and ignored, as stats are always shown");
+    }
+    // An explicit --git-dir is opened as is, otherwise search upwards from
+    // the working directory like git does, without crossing filesystems
+    let repo = match args.flag_git_dir {
+        Some(ref path) => Repository::open(path).map_err(|e| match e.code() {
+            ErrorCode::NotFound => Reworded::boxed(e, format!("not a git repository: {}", path)),
+            _ => e.into(),
+        })?,
+        None => Repository::open_ext(".", RepositoryOpenFlags::empty(), &[] as &[&OsStr]).map_err(
+            |e| match e.code() {
+                ErrorCode::NotFound => Reworded::boxed(
+                    e,
+                    "not a git repository (or any parent up to the filesystem boundary)".to_owned(),
+                ),
+                _ => e.into(),
+            },
+        )?,
+    };
+    // As with git, the working tree can live apart from the git directory
+    if let Some(ref dir) = args.flag_work_tree {
+        if !Path::new(dir).is_dir() {
+            return Err(Error::from_str(&format!("--work-tree {} is not a directory", dir)).into());
+        }
+        repo.set_workdir(Path::new(dir), false)?;
+    }
+    // Objects can also be read from a directory besides the repository's own
+    if let Some(ref dir) = args.flag_object_dir {
+        if !Path::new(dir).is_dir() {
+            return Err(
+                Error::from_str(&format!("--object-dir {} is not a directory", dir)).into(),
+            );
+        }
+        repo.odb()?.add_disk_alternate(dir)?;
+    }
+    let mut opts = args.walk_options()?;
+    // Pathspecs are relative to the current directory, unless the git
+    // directory was named without a working tree to be in
+    let prefix = if args.flag_git_dir.is_none() || args.flag_work_tree.is_some() {
+        cwd_prefix(&repo)
+    } else {
+        None
+    };
+    opts.resolve_specs(prefix.as_deref().unwrap_or(""))?;
+    if opts.allow_shallow && repo.is_shallow() {
+        eprintln!(
+            "warning: repository is shallow, so its boundary commits are diffed as root commits"
+        );
+    }
+    let template = match args.flag_template {
+        Some(ref template) => Some(template.parse::<Template>()?),
+        None => None,
+    };
+    let format = match template {
+        Some(ref template) => {
+            for column in template.columns() {
+                opts.enable_column(column);
             }
-            if let Some(n) = args.max_parents() {
-                if parents >= n {
-                    return None;
-                }
+            Format::Template
+        }
+        None => args.flag_format.parse()?,
+    };
+    if format == Format::Template && template.is_none() {
+        return Err(Error::from_str("--format template needs a --template").into());
+    }
+    if args.flag_summary && format != Format::Json && format != Format::MsgPack {
+        return Err(
+            Error::from_str("--summary is only supported for json or msgpack output").into(),
+        );
+    }
+    if args.flag_header {
+        if format != Format::Json && format != Format::MsgPack {
+            return Err(
+                Error::from_str("--header is only supported for json or msgpack output").into(),
+            );
+        }
+        if args.flag_per_file
+            || args.flag_by_author
+            || args.flag_by_extension
+            || args.flag_group_by.is_some()
+        {
+            return Err(Error::from_str("--header is only supported for commit records").into());
+        }
+    }
+    if args.flag_json_array && format != Format::Json {
+        return Err(Error::from_str("--json-array is only supported for json output").into());
+    }
+    if args.flag_null
+        && (args.flag_json_array
+            || format == Format::JsonArray
+            || format == Format::MsgPack
+            || format == Format::Table)
+    {
+        return Err(Error::from_str(
+            "--null is only supported for json lines, csv, tsv or template output",
+        )
+        .into());
+    }
+    if args.flag_per_file && format.commits_only() {
+        return Err(Error::from_str("--per-file is only supported for json output").into());
+    }
+    if args.flag_with_hunks && !args.flag_per_file {
+        return Err(Error::from_str("--with-hunks is only supported with --per-file").into());
+    }
+    if args.flag_by_author {
+        if format.commits_only() {
+            return Err(Error::from_str("--by-author is only supported for json output").into());
+        }
+        opts.with_author = true;
+    }
+    if args.flag_by_extension {
+        if format.commits_only() {
+            return Err(Error::from_str("--by-extension is only supported for json output").into());
+        }
+        if args.flag_by_author {
+            return Err(Error::from_str("--by-extension conflicts with --by-author").into());
+        }
+        opts.per_file = true;
+    }
+    let group_by = match args.flag_group_by {
+        Some(ref period) => Some(period.parse::<Period>()?),
+        None => None,
+    };
+    if group_by.is_some() {
+        if format.commits_only() {
+            return Err(Error::from_str("--group-by is only supported for json output").into());
+        }
+        if args.flag_by_author || args.flag_by_extension {
+            return Err(Error::from_str(
+                "--group-by conflicts with --by-author and --by-extension",
+            )
+            .into());
+        }
+        // Periods are worked out from the author time in seconds
+        opts.with_time = true;
+        opts.date_format = DateFormat::Epoch;
+    }
+    if args.flag_limit_bytes.is_some()
+        && (format == Format::JsonArray
+            || format == Format::Table
+            || args.flag_by_author
+            || args.flag_by_extension
+            || group_by.is_some())
+    {
+        return Err(Error::from_str(
+            "--limit-bytes can't measure output that's only written once the walk is done",
+        )
+        .into());
+    }
+    if args.flag_worktree {
+        if !opts.commits.is_empty() {
+            return Err(Error::from_str("--worktree takes no revisions").into());
+        }
+        if opts.base.is_some() {
+            return Err(Error::from_str("--worktree is always against HEAD, not a --base").into());
+        }
+        if opts.snapshot {
+            return Err(Error::from_str("--worktree conflicts with --snapshot").into());
+        }
+        if format != Format::Json && format != Format::MsgPack {
+            return Err(
+                Error::from_str("--worktree is only supported for json or msgpack output").into(),
+            );
+        }
+    }
+    if args.flag_validate {
+        // Report every problem at once, rather than just the first
+        let errors = validate(&repo, &opts);
+        for e in &errors {
+            error_format.report(e);
+        }
+        if !errors.is_empty() {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let out: Box<dyn Write> = match args.flag_output {
+        Some(ref path) => {
+            let file = File::create(path)
+                .map_err(|e| Error::from_str(&format!("couldn't create {}: {}", path, e)))?;
+            Box::new(BufWriter::new(file))
+        }
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    let written = Rc::new(Cell::new(0));
+    let mut out = Counter::new(out, written.clone());
+
+    if args.flag_worktree {
+        let mut printer = Printer::new(out, format, args.flag_json_array);
+        printer.write(Entry::Worktree(worktree_shortstat(&repo, &opts)?))?;
+        printer.finish()?;
+        return Ok(());
+    }
+
+    if args.flag_count {
+        // Commits filtered on stats can't be counted without diffing them
+        let mut count = 0;
+        if opts.filters_stats() {
+            for record in shortstats(&repo, &opts)? {
+                record?;
+                count += 1;
             }
-            if !args.arg_spec.is_empty() {
-                match commit.parents().len() {
-                    0 => {
-                        let tree = filter_try!(commit.tree());
-                        let flags = git2::PathspecFlags::NO_MATCH_ERROR;
-                        if ps.match_tree(&tree, flags).is_err() {
-                            return None;
-                        }
-                    }
-                    _ => {
-                        let m = commit.parents().all(|parent| {
-                            match_with_parent(&repo, &commit, &parent, &mut diffopts)
-                                .unwrap_or(false)
-                        });
-                        if !m {
-                            return None;
-                        }
-                    }
-                }
+        } else {
+            for commit in commits(&repo, &opts)? {
+                commit?;
+                count += 1;
             }
-            Some(Ok(commit))
-        })
-        .skip(args.flag_skip.unwrap_or(0))
-        .take(args.flag_max_count.unwrap_or(!0));
+        }
+        // As many as would be written before --limit-commits stopped them
+        if let Some(n) = args.flag_limit_commits {
+            count = count.min(n);
+        }
+        writeln!(out, "{}", count)?;
+        out.flush()?;
+        return Ok(());
+    }
 
     // print!
-    for commit in revwalk {
-        let commit = commit?;
-        if !args.flag_patch || commit.parents().len() > 1 {
-            continue;
-        }
-        let a = if commit.parents().len() == 1 {
-            let parent = commit.parent(0)?;
-            Some(parent.tree()?)
-        } else {
-            None
+    let mut printer = Printer::new(out, format, args.flag_json_array);
+    if args.flag_null {
+        printer.null_separated();
+    }
+    if format == Format::Table && args.flag_output.is_none() && io::stdout().is_terminal() {
+        printer.colored();
+    }
+    printer.header(&opts.columns())?;
+    if args.flag_header {
+        printer.write(Entry::Schema(Schema::new(&opts.columns())))?;
+    }
+    if let Some(template) = template {
+        printer.template(template);
+    }
+    // Progress goes to stderr, and only to a terminal unless forced, so it
+    // never mixes with the output
+    let progress = args.flag_progress_always || (args.flag_progress && io::stderr().is_terminal());
+    let mut summary = Summary::new();
+    let mut by_author = ByAuthor::default();
+    let mut by_extension = ByExtension::default();
+    let mut by_period = group_by.map(ByPeriod::new);
+    let mut failed = 0;
+    let mut truncated = false;
+    for record in shortstats(&repo, &opts)? {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) if args.flag_keep_going => {
+                eprintln!("warning: skipping {}", e);
+                failed += 1;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
         };
-        let b = commit.tree()?;
-        let diff = repo.diff_tree_to_tree(a.as_ref(), Some(&b), Some(&mut diffopts2))?;
-        let short_stat: ShortStat = diff.stats()?.into();
-        println!("{}", serde_json::to_string(&short_stat).unwrap());
+        // Stop only once there's more to write, so a marker means something
+        // was left out
+        if args
+            .flag_limit_commits
+            .is_some_and(|n| summary.commits >= n)
+            || args.flag_limit_bytes.is_some_and(|n| written.get() > n)
+        {
+            truncated = true;
+            break;
+        }
+        summary.add(&record.short_stat);
+        // `is_multiple_of` is too new to rely on
+        #[allow(unknown_lints, clippy::manual_is_multiple_of)]
+        let due = summary.commits % PROGRESS_INTERVAL == 0;
+        if progress && due {
+            eprint!("\rprocessed {} commits", summary.commits);
+        }
+        if args.flag_by_author {
+            by_author.add(&record);
+        } else if args.flag_by_extension {
+            by_extension.add(&record);
+        } else if let Some(ref mut by_period) = by_period {
+            by_period.add(&record);
+        } else if args.flag_per_file {
+            let id = short_id(&repo, record.oid, opts.abbrev)?;
+            for file in record.files {
+                printer.write(Entry::File(FileRecord {
+                    id: id.clone(),
+                    hunks: if args.flag_with_hunks {
+                        Some(file.hunks)
+                    } else {
+                        None
+                    },
+                    file,
+                }))?;
+            }
+        } else {
+            printer.write(Entry::Commit(Box::new(record)))?;
+        }
+    }
+    for author in by_author.finish() {
+        printer.write(Entry::Author(author))?;
+    }
+    for extension in by_extension.finish() {
+        printer.write(Entry::Extension(extension))?;
+    }
+    for period in by_period.map(ByPeriod::finish).unwrap_or_default() {
+        printer.write(Entry::Period(period))?;
     }
+    if progress {
+        eprintln!("\rprocessed {} commits", summary.commits);
+    }
+    if args.flag_summary {
+        printer.write(Entry::Summary(summary))?;
+    }
+    if truncated {
+        if format.commits_only() {
+            eprintln!("warning: output truncated by a limit");
+        } else {
+            printer.write(Entry::Truncated(Truncated::new()))?;
+        }
+    }
+    printer.finish()?;
 
+    if failed > 0 {
+        let commits = if failed == 1 { "commit" } else { "commits" };
+        return Err(Error::from_str(&format!("{} {} couldn't be diffed", failed, commits)).into());
+    }
     Ok(())
 }
 
-fn match_with_parent(
-    repo: &Repository,
-    commit: &Commit,
-    parent: &Commit,
-    opts: &mut DiffOptions,
-) -> Result<bool, Error> {
-    let a = parent.tree()?;
-    let b = commit.tree()?;
-    let diff = repo.diff_tree_to_tree(Some(&a), Some(&b), Some(opts))?;
-    Ok(diff.deltas().len() > 0)
+/// The current directory relative to the top of the working tree, with `/`
+/// separators, or `None` if it's outside the working tree.
+fn cwd_prefix(repo: &Repository) -> Option<String> {
+    let workdir = repo.workdir()?.canonicalize().ok()?;
+    let cwd = env::current_dir().ok()?.canonicalize().ok()?;
+    let prefix = cwd.strip_prefix(&workdir).ok()?;
+    let parts: Vec<_> = prefix
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy())
+        .collect();
+    Some(parts.join("/"))
 }
 
 impl Args {
@@ -197,37 +608,398 @@ impl Args {
         self.flag_max_parents
             .or(if self.flag_no_merges { Some(1) } else { None })
     }
+
+    /// Revisions from the command line, followed by any read from
+    /// `--revs-from`, one per line.
+    fn commits(&self) -> Result<Vec<String>, Error> {
+        let mut commits = self.arg_commit.clone();
+        if let Some(ref path) = self.flag_revs_from {
+            let read = if path == "-" {
+                io::read_to_string(io::stdin())
+            } else {
+                fs::read_to_string(path)
+            };
+            let revs = read.map_err(|e| {
+                Error::from_str(&format!("couldn't read revisions from {}: {}", path, e))
+            })?;
+            commits.extend(
+                revs.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(String::from),
+            );
+        }
+        Ok(commits)
+    }
+
+    /// Globs of refs to walk from, for --glob, --branches and --tags.
+    fn ref_globs(&self) -> Vec<String> {
+        let mut globs = Vec::new();
+        if self.flag_branches {
+            globs.push("refs/heads/*".to_owned());
+        }
+        if self.flag_tags {
+            globs.push("refs/tags/*".to_owned());
+        }
+        globs.extend(self.flag_glob.iter().cloned());
+        globs
+    }
+
+    fn find_renames(&self) -> Result<Option<u16>, Error> {
+        if self.flag_no_renames {
+            if self.flag_follow {
+                return Err(Error::from_str(
+                    "--follow needs renames, so conflicts with --no-renames",
+                ));
+            }
+            return Ok(None);
+        }
+        if !self.flag_find_renames {
+            return Ok(None);
+        }
+        let threshold = self.flag_rename_threshold.trim_end_matches('%');
+        match threshold.parse() {
+            Ok(threshold) if threshold <= 100 => Ok(Some(threshold)),
+            _ => Err(Error::from_str(&format!(
+                "invalid rename threshold: {}",
+                self.flag_rename_threshold
+            ))),
+        }
+    }
+
+    fn walk_options(&self) -> Result<WalkOptions, Error> {
+        if self.flag_pathspec_glob && self.flag_literal_pathspecs {
+            return Err(Error::from_str(
+                "--pathspec-glob conflicts with --literal-pathspecs",
+            ));
+        }
+        if self.flag_invert_grep && self.flag_grep.is_none() {
+            return Err(Error::from_str("--invert-grep needs a --grep"));
+        }
+        if self
+            .flag_sample
+            .is_some_and(|rate| !(0.0..=1.0).contains(&rate))
+        {
+            return Err(Error::from_str("--sample must be from 0.0 to 1.0"));
+        }
+        if self.flag_window == Some(0) {
+            return Err(Error::from_str("--window must be at least 1"));
+        }
+        if self.flag_tips_only
+            && (self.flag_merge_base.is_some() || self.flag_follow || self.flag_ancestry_path)
+        {
+            return Err(Error::from_str(
+                "--tips-only conflicts with --merge-base, --follow and --ancestry-path",
+            ));
+        }
+        if self.flag_tips_only {
+            let filters = [
+                (self.flag_author.is_some(), "--author"),
+                (self.flag_committer.is_some(), "--committer"),
+                (self.flag_author_regex.is_some(), "--author-regex"),
+                (self.flag_committer_regex.is_some(), "--committer-regex"),
+                (self.flag_grep.is_some(), "--grep"),
+                (self.flag_since.is_some(), "--since"),
+                (self.flag_until.is_some(), "--until"),
+                (self.flag_merges, "--merges"),
+                (self.flag_no_merges, "--no-merges"),
+                (self.flag_min_parents.is_some(), "--min-parents"),
+                (self.flag_max_parents.is_some(), "--max-parents"),
+                (self.flag_path_regex.is_some(), "--path-regex"),
+                (self.flag_changed_path.is_some(), "--changed-path"),
+                (self.flag_sample.is_some(), "--sample"),
+                (!self.arg_spec.is_empty(), "pathspecs"),
+            ];
+            if let Some((_, filter)) = filters.iter().find(|(set, _)| *set) {
+                return Err(Error::from_str(&format!(
+                    "--tips-only diffs every revision given, so conflicts with {}",
+                    filter
+                )));
+            }
+        }
+        if self.flag_snapshot && self.flag_base.is_some() {
+            return Err(Error::from_str("--snapshot conflicts with --base"));
+        }
+        if self.flag_ignore_submodules && self.flag_submodules_only {
+            return Err(Error::from_str(
+                "--submodules-only conflicts with --ignore-submodules",
+            ));
+        }
+        Ok(WalkOptions {
+            commits: self.commits()?,
+            ref_globs: self.ref_globs(),
+            all: self.flag_all,
+            ancestry_path: self.flag_ancestry_path,
+            merge_base: self.flag_merge_base.clone(),
+            tips_only: self.flag_tips_only,
+            base: self.flag_base.clone(),
+            snapshot: self.flag_snapshot,
+            specs: self.arg_spec.clone(),
+            literal_pathspecs: self.flag_literal_pathspecs,
+            pathspec_icase: self.flag_pathspec_icase,
+            pathspec_glob: self.flag_pathspec_glob,
+            strict_pathspec: self.flag_strict_pathspec,
+            follow: self.flag_follow,
+            topo_order: self.flag_topo_order,
+            stable: self.flag_stable,
+            date_order: self.flag_date_order || self.flag_committer_date_order,
+            author_date_order: self.flag_author_date_order,
+            reverse: self.flag_reverse,
+            first_parent: self.flag_first_parent,
+            allow_shallow: self.flag_allow_shallow,
+            skip: self.flag_skip.unwrap_or(0),
+            max_count: self.flag_max_count,
+            min_insertions: self.flag_min_insertions,
+            max_insertions: self.flag_max_insertions,
+            min_deletions: self.flag_min_deletions,
+            max_deletions: self.flag_max_deletions,
+            min_churn: self.flag_min_churn,
+            max_churn: self.flag_max_churn,
+            skip_empty: self.flag_skip_empty,
+            binary_only: self.flag_binary_only,
+            ignore_generated: self.flag_ignore_generated.clone(),
+            ignore_submodules: self.flag_ignore_submodules,
+            submodules_only: self.flag_submodules_only,
+            diff_filter: match self.flag_diff_filter {
+                Some(ref filter) => Some(filter.parse()?),
+                None => None,
+            },
+            sort_output: match self.flag_sort_output {
+                Some(ref key) => Some(key.parse()?),
+                None => None,
+            },
+            min_parents: self.min_parents(),
+            max_parents: self.max_parents(),
+            author: self.flag_author.clone(),
+            committer: self.flag_committer.clone(),
+            author_regex: self.flag_author_regex.clone(),
+            committer_regex: self.flag_committer_regex.clone(),
+            grep: self.flag_grep.clone(),
+            invert_grep: self.flag_invert_grep,
+            path_regex: self.flag_path_regex.clone(),
+            changed_path: self.flag_changed_path.clone(),
+            sample: self.flag_sample,
+            since: match self.flag_since {
+                Some(ref since) => Some(date::parse(since, false)?),
+                None => None,
+            },
+            until: match self.flag_until {
+                Some(ref until) => Some(date::parse(until, true)?),
+                None => None,
+            },
+            with_id: self.flag_with_id,
+            abbrev: self.flag_abbrev,
+            with_author: self.flag_with_author,
+            use_mailmap: self.flag_use_mailmap,
+            with_time: self.flag_with_time,
+            date_format: self.flag_date_format.parse()?,
+            with_delta_time: self.flag_with_delta_time,
+            with_binary_count: self.flag_with_binary_count,
+            with_dirs: self.flag_with_dirs,
+            with_net: self.flag_verify_net,
+            with_churn: self.flag_with_churn,
+            with_ratios: self.flag_with_ratios,
+            with_bytes: self.flag_objects,
+            with_parents: self.flag_with_parents,
+            with_tree: self.flag_with_tree,
+            with_root_flag: self.flag_with_root_flag,
+            decorate: self.flag_decorate,
+            with_subject: self.flag_with_subject,
+            subject_maxlen: self.flag_subject_maxlen,
+            encoding: match self.flag_encoding {
+                Some(ref encoding) => Some(encoding.parse()?),
+                None => None,
+            },
+            number: self.flag_number,
+            cumulative: self.flag_cumulative,
+            window: self.flag_window,
+            per_file: self.flag_per_file,
+            ignore_whitespace: self.flag_ignore_whitespace,
+            ignore_whitespace_change: self.flag_ignore_whitespace_change,
+            ignore_whitespace_eol: self.flag_ignore_whitespace_eol,
+            diff_algorithm: self.flag_diff_algorithm.parse()?,
+            context_lines: self.flag_context,
+            interhunk_lines: self.flag_inter_hunk_context,
+            merge_diff: self.flag_merge_diff.parse()?,
+            find_renames: self.find_renames()?,
+            jobs: self.flag_jobs.unwrap_or(1),
+            object_dir: match self.flag_object_dir {
+                // Diff threads open the repository from its own directory
+                Some(ref dir) => Some(
+                    fs::canonicalize(dir)
+                        .map_err(|e| Error::from_str(&format!("--object-dir {}: {}", dir, e)))?
+                        .to_string_lossy()
+                        .into_owned(),
+                ),
+                None => None,
+            },
+        })
+    }
 }
 
 fn main() {
     const USAGE: &str = "
-usage: log [options] [<commit>..] [--] [<spec>..]
+usage: log [options] [--glob <pattern>]... [--ignore-generated <glob>]... [--] [<arg>...]
+
+Each <arg> is a revision to walk, or a pathspec after --.
 
 Options:
+    --glob <pattern>        walk from every ref matching <pattern>, such as 'refs/tags/*',
+                            as well as any revisions given; repeatable
+    --all                   walk from every ref, and HEAD
+    --branches              walk from every branch, as --glob 'refs/heads/*'
+    --tags                  walk from every tag, as --glob 'refs/tags/*'
     --topo-order            sort commits in topological order
-    --date-order            sort commits in date order
+    --stable                sort commits topologically, then by date and id, the same
+                            whatever the order of revisions, buffering them all first
+    --date-order            sort commits by committer date, ties by id
+    --committer-date-order  the same as --date-order
+    --author-date-order     sort commits by author date, buffering them all first
     --reverse               sort commits in reverse
-    --author <user>         author to sort by
-    --committer <user>      committer to sort by
-    --grep <pat>            pattern to filter commit messages by
-    --git-dir <dir>         alternative git directory to use
+    --first-parent          follow only the first parent of merge commits
+    --ancestry-path         only show commits descended from a hidden revision, such
+                            as A of A..B, so on a path between the two
+    --allow-shallow         walk a shallow clone, its boundary commits as roots
+    --author <user>         only show commits whose author name or email contains <user>
+    --committer <user>      only show commits whose committer name or email contains <user>
+    --author-regex <pat>    regex to match the author against, as Name <email>
+    --committer-regex <pat>  regex to match the committer against, as Name <email>
+    --grep <pat>            regex to filter commit messages by
+    --invert-grep           only show commits whose messages don't match --grep
+    --path-regex <pat>      only show commits changing a path matching regex <pat>
+    --sample <rate>         only show a fraction <rate> of commits, from 0.0 to 1.0,
+                            picked by id so every run picks the same ones
+    --changed-path <path>   only show commits changing the file or tree at <path>,
+                            compared by id without diffing
+    --since <date>          only show commits committed at or after <date>
+    --until <date>          only show commits committed at or before <date>
+    --revs-from <file>      also walk revisions from <file>, one per line, or - for stdin
+    --merge-base <rev>      walk only commits since the revisions forked from <rev>
+    --tips-only             diff just the revisions given, in order, without walking history
+    --base <rev>            diff every commit against <rev>, not its parent
+    --snapshot              count the whole tree of every commit, as if all inserted
+    --literal-pathspecs     match each pathspec as a plain path rather than a glob
+    --pathspec-icase        match each pathspec ignoring case
+    --pathspec-glob         match each pathspec as a glob where * stops at /
+    --strict-pathspec       fail if a pathspec matches no file in the walked history
+    --follow                follow a single pathspec back through renames
+    --git-dir <dir>         git directory to use, rather than searching upwards
+    --work-tree <dir>       working tree to use, rather than the repository's own
+    --object-dir <dir>      also read objects from <dir>, as an alternate
     --skip <n>              number of commits to skip
-    -n, --max-count <n>     maximum number of commits to show
+    -n, --max-count <n>     maximum number of commits to show, also written -n<n>
+    --limit-commits <n>     stop after <n> records, ending with a truncated record
+    --limit-bytes <n>       stop once output passes <n> bytes, ending with a truncated record
+    --min-insertions <n>    drop commits with fewer than <n> insertions
+    --max-insertions <n>    drop commits with more than <n> insertions
+    --min-deletions <n>     drop commits with fewer than <n> deletions
+    --max-deletions <n>     drop commits with more than <n> deletions
+    --min-churn <n>         drop commits with fewer than <n> insertions plus deletions
+    --max-churn <n>         drop commits with more than <n> insertions plus deletions
+    --binary-only           keep only commits changing nothing but binary files
+    --ignore-generated <glob>  leave files matching <glob> out of the stats, though
+                            still walking commits changing them; repeatable
+    --ignore-submodules     leave submodule changes out of the stats
+    --submodules-only       count only submodule changes, keeping commits with some
+    --diff-filter <kinds>   count only files added, copied, deleted, modified, renamed or
+                            type changed, as any of ACDMRT, or leave them out in lowercase
+    --skip-empty            drop commits that change nothing, which are kept by default
     --merges                only show merge commits
     --no-merges             don't show merge commits
     --no-min-parents        don't require a minimum number of parents
     --no-max-parents        don't require a maximum number of parents
     --max-parents <n>       specify a maximum number of parents for a commit
     --min-parents <n>       specify a minimum number of parents for a commit
-    -p, --patch             show commit diff
+    -p, --patch             deprecated and ignored, as stats are always shown
+    --with-id               include the commit id in each record
+    --abbrev <n>            abbreviate commit ids to <n> digits, or more to keep them unique
+    --with-author           include the author name and email in each record
+    --use-mailmap           canonicalize author names and emails with the mailmap
+    --with-time             include the author time and utc offset in each record
+    --date-format <fmt>     write times as epoch seconds, rfc3339 or iso [default: epoch]
+    --with-delta-time       include the seconds since the first parent was committed, as dt
+    --with-binary-count     include the number of binary files in each record
+    --with-dirs             include the number of directories with changed files
+    --verify-net            include the net change, insertions less deletions, as net
+    --with-churn            include the lines changed, insertions plus deletions, as churn
+    --with-ratios           include insertions and deletions as fractions of lines changed
+    --objects               include the change in size of changed blobs, as bytes
+    --with-parents          include the ids of the parents of each commit
+    --with-tree             include the id of the tree of each commit
+    --with-root-flag        include whether each commit is a root commit, without parents
+    --decorate              include the names of refs pointing at each commit
+    --with-subject          include the first line of each commit message
+    --subject-maxlen <n>    truncate subjects to <n> characters
+    --encoding <name>       decode messages and authors from utf-8, iso-8859-1 or
+                            windows-1252, rather than as each commit declares
+    --number                include the position of each record in the output
+    --cumulative            include the insertions and deletions so far as ci and cd
+    --window <n>            include the mean insertions and deletions of the last <n>
+                            records as ima and dma
+    --sort-output <key>     buffer records and emit them sorted by commit-date,
+                            author-date, insertions, deletions, churn or files,
+                            descending
+    --format <fmt>          output format, one of json, json-array, csv, tsv, template,
+                            msgpack or table [default: json]
+    --error-format <fmt>    report a failure as human or json, an object with its
+                            error, code and class [default: human]
+    --template <str>        lay out each record like '{id} {insertions}', implying
+                            the template format
+    --ignore-whitespace     ignore all whitespace when comparing lines
+    --ignore-whitespace-change  ignore changes in the amount of whitespace
+    --ignore-whitespace-eol  ignore whitespace at the end of lines
+    --diff-algorithm <alg>  diff lines with myers, minimal or patience [default: myers]
+    -U, --context <n>       lines of context around each hunk, by default 3
+    --inter-hunk-context <n>  unchanged lines between hunks that still join them
+    --merge-diff <mode>     diff merges against their first-parent or second-parent,
+                            or as a combined diff [default: first-parent]
+    -M, --find-renames      detect renames, counting only their edited lines
+    --no-renames            never detect renames, overriding --find-renames
+    --rename-threshold <pct>  similarity needed to count as a rename [default: 50%]
+    --per-file              emit a record per changed file instead of per commit
+    --with-hunks            include the number of hunks in each per-file record, as h
+    -j, --jobs <n>          number of threads to compute diffs on
+    --keep-going            warn of commits that can't be diffed and carry on, exiting
+                            non-zero at the end
+    -o, --output <path>     write output to <path> rather than stdout
+    -z, --null              separate records with NUL bytes rather than newlines
+    --json-array            wrap json output in an array, still streaming each record
+    --count                 only print the number of matching commits, without diffing
+    --worktree              print the stats of uncommitted changes against HEAD instead
+    --validate              check the arguments resolve, printing nothing if they do
+    --by-author             emit totals per author, rather than per commit
+    --by-extension          emit totals per file extension, rather than per commit
+    --group-by <period>     emit totals per day, week or month of author time, oldest first
+    --header                start with a record listing the fields of the rest
+    --summary               finish with a record totalling all emitted commits
+    --progress              report the number of commits processed on stderr, if a terminal
+    --progress-always       report progress on stderr, even if not a terminal
     -h, --help              show this message
 ";
 
-    let args = Docopt::new(USAGE)
+    let mut args: Args = Docopt::new(USAGE)
         .and_then(|d| d.deserialize())
         .unwrap_or_else(|e| e.exit());
-    match run(&args) {
+    // Docopt drops the `--` separating revisions from pathspecs, so split the
+    // arguments where it was
+    let specs = env::args().skip_while(|arg| arg != "--").skip(1).count();
+    let commits = args.arg_arg.len().saturating_sub(specs);
+    args.arg_spec = args.arg_arg.split_off(commits);
+    args.arg_commit = mem::take(&mut args.arg_arg);
+    let error_format = args
+        .flag_error_format
+        .parse::<ErrorFormat>()
+        .unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        });
+    match run(&args, error_format) {
         Ok(()) => {}
-        Err(e) => println!("error: {}", e),
+        // The reader has all it wanted, as with `| head`, so stop quietly
+        Err(ref e) if broken_pipe(&**e) => {}
+        Err(e) => {
+            error_format.report(&*e);
+            process::exit(1);
+        }
     }
 }
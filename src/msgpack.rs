@@ -0,0 +1,230 @@
+//! A minimal MessagePack encoder for JSON values.
+//!
+//! Records are small and flat, so encoding via `serde_json::Value` keeps the
+//! field names identical to the JSON output without a full serde backend.
+
+use serde_json::Value;
+use std::io::{self, Write};
+
+/// Write `value` as a single MessagePack object.
+pub fn write_value<W: Write>(out: &mut W, value: &Value) -> io::Result<()> {
+    match value {
+        Value::Null => out.write_all(&[0xc0]),
+        Value::Bool(false) => out.write_all(&[0xc2]),
+        Value::Bool(true) => out.write_all(&[0xc3]),
+        Value::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                write_uint(out, n)
+            } else if let Some(n) = n.as_i64() {
+                write_int(out, n)
+            } else {
+                out.write_all(&[0xcb])?;
+                out.write_all(&n.as_f64().unwrap_or_default().to_be_bytes())
+            }
+        }
+        Value::String(s) => {
+            write_length(out, s.len(), 0xa0, 32, [0xd9, 0xda, 0xdb])?;
+            out.write_all(s.as_bytes())
+        }
+        Value::Array(values) => {
+            write_length(out, values.len(), 0x90, 16, [0xdc, 0xdc, 0xdd])?;
+            values.iter().try_for_each(|value| write_value(out, value))
+        }
+        Value::Object(map) => {
+            write_length(out, map.len(), 0x80, 16, [0xde, 0xde, 0xdf])?;
+            map.iter().try_for_each(|(key, value)| {
+                write_value(out, &Value::String(key.clone()))?;
+                write_value(out, value)
+            })
+        }
+    }
+}
+
+fn write_uint<W: Write>(out: &mut W, n: u64) -> io::Result<()> {
+    if n < 0x80 {
+        out.write_all(&[n as u8])
+    } else if n <= u64::from(u8::MAX) {
+        out.write_all(&[0xcc, n as u8])
+    } else if n <= u64::from(u16::MAX) {
+        out.write_all(&[0xcd])?;
+        out.write_all(&(n as u16).to_be_bytes())
+    } else if n <= u64::from(u32::MAX) {
+        out.write_all(&[0xce])?;
+        out.write_all(&(n as u32).to_be_bytes())
+    } else {
+        out.write_all(&[0xcf])?;
+        out.write_all(&n.to_be_bytes())
+    }
+}
+
+/// Only called for negative numbers, as positive ones are unsigned.
+fn write_int<W: Write>(out: &mut W, n: i64) -> io::Result<()> {
+    if n >= -32 {
+        out.write_all(&[n as u8])
+    } else if n >= i64::from(i8::MIN) {
+        out.write_all(&[0xd0, n as u8])
+    } else if n >= i64::from(i16::MIN) {
+        out.write_all(&[0xd1])?;
+        out.write_all(&(n as i16).to_be_bytes())
+    } else if n >= i64::from(i32::MIN) {
+        out.write_all(&[0xd2])?;
+        out.write_all(&(n as i32).to_be_bytes())
+    } else {
+        out.write_all(&[0xd3])?;
+        out.write_all(&n.to_be_bytes())
+    }
+}
+
+/// Write a length header, as a fixed size type if under `fix_limit` or else
+/// the 8, 16 or 32 bit marker given. Arrays and maps have no 8 bit form, so
+/// pass their 16 bit marker twice.
+fn write_length<W: Write>(
+    out: &mut W,
+    len: usize,
+    fix_marker: u8,
+    fix_limit: usize,
+    markers: [u8; 3],
+) -> io::Result<()> {
+    if len < fix_limit {
+        out.write_all(&[fix_marker | len as u8])
+    } else if len <= usize::from(u8::MAX) && markers[0] != markers[1] {
+        out.write_all(&[markers[0], len as u8])
+    } else if len <= usize::from(u16::MAX) {
+        out.write_all(&[markers[1]])?;
+        out.write_all(&(len as u16).to_be_bytes())
+    } else {
+        out.write_all(&[markers[2]])?;
+        out.write_all(&(len as u32).to_be_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn encode(value: Value) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_value(&mut out, &value).unwrap();
+        out
+    }
+
+    /// The encoding of a header followed by `len` bytes, elements or entries,
+    /// checking only the header.
+    fn header(value: Value, len: usize) -> Vec<u8> {
+        let out = encode(value);
+        out[..out.len() - len].to_vec()
+    }
+
+    #[test]
+    fn encodes_nil_and_bools() {
+        assert_eq!(encode(json!(null)), [0xc0]);
+        assert_eq!(encode(json!(false)), [0xc2]);
+        assert_eq!(encode(json!(true)), [0xc3]);
+    }
+
+    #[test]
+    fn encodes_unsigned_ints_in_the_smallest_width() {
+        assert_eq!(encode(json!(0)), [0x00]);
+        assert_eq!(encode(json!(0x7f)), [0x7f]);
+        assert_eq!(encode(json!(0x80)), [0xcc, 0x80]);
+        assert_eq!(encode(json!(0xff)), [0xcc, 0xff]);
+        assert_eq!(encode(json!(0x100)), [0xcd, 0x01, 0x00]);
+        assert_eq!(encode(json!(0xffff)), [0xcd, 0xff, 0xff]);
+        assert_eq!(encode(json!(0x1_0000)), [0xce, 0x00, 0x01, 0x00, 0x00]);
+        assert_eq!(encode(json!(u32::MAX)), [0xce, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(
+            encode(json!(u64::from(u32::MAX) + 1)),
+            [0xcf, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn encodes_negative_ints_in_the_smallest_width() {
+        assert_eq!(encode(json!(-1)), [0xff]);
+        assert_eq!(encode(json!(-32)), [0xe0]);
+        assert_eq!(encode(json!(-33)), [0xd0, 0xdf]);
+        assert_eq!(encode(json!(-128)), [0xd0, 0x80]);
+        assert_eq!(encode(json!(-129)), [0xd1, 0xff, 0x7f]);
+        assert_eq!(encode(json!(-32768)), [0xd1, 0x80, 0x00]);
+        assert_eq!(encode(json!(-32769)), [0xd2, 0xff, 0xff, 0x7f, 0xff]);
+        assert_eq!(encode(json!(i32::MIN)), [0xd2, 0x80, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            encode(json!(i64::from(i32::MIN) - 1)),
+            [0xd3, 0xff, 0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn encodes_floats_as_f64() {
+        assert_eq!(
+            encode(json!(1.5)),
+            [0xcb, 0x3f, 0xf8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            encode(json!(-0.25)),
+            [0xcb, 0xbf, 0xd0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn encodes_strings_with_their_byte_length() {
+        let string = |len| json!("x".repeat(len));
+        assert_eq!(encode(json!("")), [0xa0]);
+        assert_eq!(encode(json!("ab")), [0xa2, b'a', b'b']);
+        assert_eq!(encode(json!("é")), [0xa2, 0xc3, 0xa9]);
+        assert_eq!(header(string(31), 31), [0xbf]);
+        assert_eq!(header(string(32), 32), [0xd9, 0x20]);
+        assert_eq!(header(string(0xff), 0xff), [0xd9, 0xff]);
+        assert_eq!(header(string(0x100), 0x100), [0xda, 0x01, 0x00]);
+        assert_eq!(header(string(0xffff), 0xffff), [0xda, 0xff, 0xff]);
+        assert_eq!(
+            header(string(0x1_0000), 0x1_0000),
+            [0xdb, 0x00, 0x01, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn encodes_arrays_without_an_8_bit_length() {
+        let array = |len| Value::Array(vec![json!(0); len]);
+        assert_eq!(encode(json!([])), [0x90]);
+        assert_eq!(encode(json!([1, "a"])), [0x92, 0x01, 0xa1, b'a']);
+        assert_eq!(header(array(15), 15), [0x9f]);
+        assert_eq!(header(array(16), 16), [0xdc, 0x00, 0x10]);
+        assert_eq!(header(array(0xffff), 0xffff), [0xdc, 0xff, 0xff]);
+        assert_eq!(
+            header(array(0x1_0000), 0x1_0000),
+            [0xdd, 0x00, 0x01, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn encodes_maps_without_an_8_bit_length() {
+        // Each entry is a two digit key, three bytes as a fixstr, and a nil
+        let map = |len| {
+            Value::Object(
+                (0..len)
+                    .map(|i| (format!("{:02}", i), Value::Null))
+                    .collect(),
+            )
+        };
+        assert_eq!(encode(json!({})), [0x80]);
+        assert_eq!(
+            encode(json!({"f": 1, "i": 2})),
+            [0x82, 0xa1, b'f', 0x01, 0xa1, b'i', 0x02]
+        );
+        assert_eq!(header(map(15), 15 * 4), [0x8f]);
+        assert_eq!(header(map(16), 16 * 4), [0xde, 0x00, 0x10]);
+    }
+
+    #[test]
+    fn encodes_records_with_nested_values() {
+        assert_eq!(
+            encode(json!({"p": ["ab"], "dt": null, "root": true})),
+            [
+                0x83, 0xa2, b'd', b't', 0xc0, 0xa1, b'p', 0x91, 0xa2, b'a', b'b', 0xa4, b'r', b'o',
+                b'o', b't', 0xc3
+            ]
+        );
+    }
+}
@@ -0,0 +1,570 @@
+//! Output formats for records.
+
+use crate::aggregate::{AuthorStat, ExtensionStat, PeriodStat};
+use crate::files::FileRecord;
+use crate::msgpack;
+use crate::{field, Record, ShortStat, Summary, COLUMNS};
+use git2::Error;
+use serde_derive::Serialize;
+use std::cell::Cell;
+use std::io::{self, Write};
+use std::mem;
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// How records are written out.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Format {
+    /// One JSON object per line.
+    #[default]
+    Json,
+    /// A single pretty printed JSON array. Every record is buffered in
+    /// memory until the walk is complete.
+    JsonArray,
+    /// A header row followed by one comma separated row per record.
+    Csv,
+    /// A header row followed by one tab separated row per record.
+    Tsv,
+    /// One line per record, laid out by a `Template`.
+    Template,
+    /// A stream of MessagePack objects, with the same fields as JSON.
+    MsgPack,
+    /// An aligned table for reading in a terminal. Every record is buffered
+    /// to size the columns.
+    Table,
+}
+
+impl Format {
+    /// Whether only commit records can be written in this format.
+    pub fn commits_only(self) -> bool {
+        matches!(
+            self,
+            Format::Csv | Format::Tsv | Format::Template | Format::Table
+        )
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "json-array" => Ok(Format::JsonArray),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            "template" => Ok(Format::Template),
+            "msgpack" => Ok(Format::MsgPack),
+            "table" => Ok(Format::Table),
+            _ => Err(Error::from_str(&format!("unknown format: {}", s))),
+        }
+    }
+}
+
+/// Join fields into a CSV row, quoting any that need it as per RFC 4180.
+/// Fields with leading or trailing whitespace are quoted too, as some readers
+/// trim unquoted fields.
+pub fn csv_row<S: AsRef<str>>(fields: &[S]) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            let field = field.as_ref();
+            if field.contains(&[',', '"', '\n', '\r'][..]) || field.trim() != field {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Join fields into a TSV row. Fields can't contain tabs or newlines, so those
+/// are escaped as `\t`, `\n` and `\r`, and backslashes as `\\`.
+pub fn tsv_row<S: AsRef<str>>(fields: &[S]) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            let mut escaped = String::new();
+            for c in field.as_ref().chars() {
+                match c {
+                    '\t' => escaped.push_str("\\t"),
+                    '\n' => escaped.push_str("\\n"),
+                    '\r' => escaped.push_str("\\r"),
+                    '\\' => escaped.push_str("\\\\"),
+                    c => escaped.push(c),
+                }
+            }
+            escaped
+        })
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
+/// Lay out rows as a table under a header of `columns`, padding each column to
+/// its widest cell. Columns of numbers are right aligned, though not ids which
+/// happen to be all digits, and with `color` insertions are green and
+/// deletions red.
+fn table(columns: &[&str], rows: &[Vec<String>], color: bool) -> Vec<String> {
+    let numeric: Vec<_> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            !matches!(*column, "id" | "parents" | "tree")
+                && rows
+                    .iter()
+                    .all(|row| row[i].is_empty() || row[i].parse::<f64>().is_ok_and(f64::is_finite))
+        })
+        .collect();
+    let widths: Vec<_> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain(Some(column.len()))
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let header: Vec<_> = columns.iter().map(|column| column.to_string()).collect();
+    let layout = |row: &[String], color: bool| {
+        let cells: Vec<_> = row
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                let pad = " ".repeat(widths[i] - cell.chars().count());
+                let cell = match columns[i] {
+                    "insertions" if color => format!("\x1b[32m{}\x1b[0m", cell),
+                    "deletions" if color => format!("\x1b[31m{}\x1b[0m", cell),
+                    _ => cell.clone(),
+                };
+                if numeric[i] {
+                    pad + &cell
+                } else {
+                    cell + &pad
+                }
+            })
+            .collect();
+        cells.join("  ").trim_end().to_owned()
+    };
+    Some(layout(&header, false))
+        .into_iter()
+        .chain(rows.iter().map(|row| layout(row, color)))
+        .collect()
+}
+
+/// A user supplied line layout, such as `"{id}\t{insertions}"`.
+///
+/// Placeholders are any of `COLUMNS`, plus `{author}` for the author name and
+/// `{date}` for the time. `{{` and `}}` write literal braces, and `\t`, `\n`
+/// and `\\` the characters they stand for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Column(&'static str),
+}
+
+impl Template {
+    /// Columns the template refers to, which records must carry.
+    pub fn columns(&self) -> Vec<&'static str> {
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                Part::Column(column) => Some(*column),
+                Part::Literal(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn render(&self, record: &Record) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => literal.clone(),
+                Part::Column(column) => record.value(column).unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+impl FromStr for Template {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| Error::from_str(&format!("invalid template: {}", reason));
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('{') if name.is_empty() => {
+                                literal.push('{');
+                                break;
+                            }
+                            Some('}') => {
+                                let column = match name.as_str() {
+                                    "author" => "author_name",
+                                    "date" => "time",
+                                    name => COLUMNS
+                                        .iter()
+                                        .find(|column| **column == name)
+                                        .ok_or_else(|| {
+                                            invalid(&format!("unknown placeholder {{{}}}", name))
+                                        })?,
+                                };
+                                if !literal.is_empty() {
+                                    parts.push(Part::Literal(mem::take(&mut literal)));
+                                }
+                                parts.push(Part::Column(column));
+                                break;
+                            }
+                            Some(c) => name.push(c),
+                            None => return Err(invalid("unclosed {")),
+                        }
+                    }
+                }
+                '}' => match chars.next() {
+                    Some('}') => literal.push('}'),
+                    _ => return Err(invalid("unmatched }")),
+                },
+                '\\' => match chars.next() {
+                    Some('t') => literal.push('\t'),
+                    Some('n') => literal.push('\n'),
+                    Some('\\') => literal.push('\\'),
+                    Some(c) => {
+                        literal.push('\\');
+                        literal.push(c);
+                    }
+                    None => literal.push('\\'),
+                },
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Self { parts })
+    }
+}
+
+/// Describes the fields records carry, so a stream can be read without
+/// knowing which options made it.
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct Schema {
+    pub schema: Vec<&'static str>,
+}
+
+impl Schema {
+    /// The keys of `columns`, as serialized.
+    pub fn new(columns: &[&str]) -> Self {
+        Self {
+            schema: columns.iter().filter_map(|column| field(column)).collect(),
+        }
+    }
+}
+
+/// Marks output cut short by a limit, as its last record.
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct Truncated {
+    pub truncated: bool,
+}
+
+impl Truncated {
+    pub fn new() -> Self {
+        Self { truncated: true }
+    }
+}
+
+impl Default for Truncated {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts the bytes written through it into a shared total, so output can be
+/// measured while a `Printer` owns the writer.
+pub struct Counter<W: Write> {
+    inner: W,
+    count: Rc<Cell<usize>>,
+}
+
+impl<W: Write> Counter<W> {
+    pub fn new(inner: W, count: Rc<Cell<usize>>) -> Self {
+        Self { inner, count }
+    }
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count.set(self.count.get() + n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A single entry of output.
+#[derive(Clone, Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Entry {
+    Schema(Schema),
+    Commit(Box<Record>),
+    Worktree(ShortStat),
+    File(FileRecord),
+    Author(AuthorStat),
+    Extension(ExtensionStat),
+    Period(PeriodStat),
+    Summary(Summary),
+    Truncated(Truncated),
+}
+
+/// Writes entries to `out` in a chosen format.
+///
+/// Call `finish` once done, to complete the output and flush it.
+pub struct Printer<W: Write> {
+    out: W,
+    format: Format,
+    json_array: bool,
+    columns: Vec<&'static str>,
+    template: Option<Template>,
+    null: bool,
+    color: bool,
+    lines: usize,
+    written: usize,
+    buffered: Vec<Entry>,
+    rows: Vec<Vec<String>>,
+}
+
+impl<W: Write> Printer<W> {
+    /// With `json_array` set, JSON output is streamed as a single array.
+    pub fn new(out: W, format: Format, json_array: bool) -> Self {
+        Self {
+            out,
+            format,
+            json_array,
+            columns: Vec::new(),
+            template: None,
+            null: false,
+            color: false,
+            lines: 0,
+            written: 0,
+            buffered: Vec::new(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Lay out each line with `template`, for `Format::Template`.
+    pub fn template(&mut self, template: Template) {
+        self.template = Some(template);
+    }
+
+    /// Separate lines of JSON, CSV and template output with NUL bytes rather
+    /// than ending each with a newline, like `git log -z`.
+    pub fn null_separated(&mut self) {
+        self.null = true;
+    }
+
+    /// Colour insertions and deletions in table output.
+    pub fn colored(&mut self) {
+        self.color = true;
+    }
+
+    /// Set the columns of tabular output, writing a header row if the format
+    /// has one.
+    pub fn header(&mut self, columns: &[&'static str]) -> io::Result<()> {
+        self.columns = columns.to_vec();
+        match self.format {
+            Format::Csv => self.line(csv_row(columns).as_bytes())?,
+            Format::Tsv => self.line(tsv_row(columns).as_bytes())?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Write a line of line oriented output.
+    fn line(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.null && self.lines > 0 {
+            self.out.write_all(b"\0")?;
+        }
+        self.out.write_all(line)?;
+        if !self.null {
+            self.out.write_all(b"\n")?;
+        }
+        self.lines += 1;
+        Ok(())
+    }
+
+    pub fn write(&mut self, entry: Entry) -> io::Result<()> {
+        match (self.format, entry) {
+            (Format::Json, entry) => {
+                if self.json_array {
+                    self.out
+                        .write_all(if self.written == 0 { b"[" } else { b",\n" })?;
+                    serde_json::to_writer(&mut self.out, &entry)?;
+                } else {
+                    self.line(&serde_json::to_vec(&entry)?)?;
+                }
+            }
+            (Format::JsonArray, entry) => self.buffered.push(entry),
+            (Format::MsgPack, entry) => {
+                msgpack::write_value(&mut self.out, &serde_json::to_value(&entry)?)?
+            }
+            (Format::Csv, Entry::Commit(record)) => {
+                let values = self.values(&record);
+                self.line(csv_row(&values).as_bytes())?
+            }
+            (Format::Tsv, Entry::Commit(record)) => {
+                let values = self.values(&record);
+                self.line(tsv_row(&values).as_bytes())?
+            }
+            (Format::Table, Entry::Commit(record)) => {
+                let values = self.values(&record);
+                self.rows.push(values)
+            }
+            (Format::Template, Entry::Commit(record)) => {
+                let template = self.template.as_ref().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "no template given")
+                })?;
+                let line = template.render(&record);
+                self.line(line.as_bytes())?
+            }
+            (Format::Csv, _) | (Format::Tsv, _) | (Format::Template, _) | (Format::Table, _) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "only commit records can be written in this format",
+                ))
+            }
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    /// A record's value for each of the columns, empty where it has none.
+    fn values(&self, record: &Record) -> Vec<String> {
+        self.columns
+            .iter()
+            .map(|column| record.value(column).unwrap_or_default())
+            .collect()
+    }
+
+    /// Complete the output, flush it and hand back the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.format {
+            Format::Json if self.json_array => {
+                self.out
+                    .write_all(if self.written == 0 { b"[]\n" } else { b"]\n" })?;
+            }
+            Format::JsonArray => {
+                let buffered = mem::take(&mut self.buffered);
+                serde_json::to_writer_pretty(&mut self.out, &buffered)?;
+                self.out.write_all(b"\n")?;
+            }
+            Format::Table => {
+                for line in table(&self.columns, &self.rows, self.color) {
+                    self.line(line.as_bytes())?;
+                }
+            }
+            _ => {}
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(commits: usize) -> Entry {
+        let mut summary = Summary::new();
+        for _ in 0..commits {
+            summary.add(&Default::default());
+        }
+        Entry::Summary(summary)
+    }
+
+    fn print(format: Format, json_array: bool, entries: Vec<Entry>) -> String {
+        let mut printer = Printer::new(Vec::new(), format, json_array);
+        printer.header(&["a", "b"]).unwrap();
+        for entry in entries {
+            printer.write(entry).unwrap();
+        }
+        String::from_utf8(printer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn printer_writes_json_lines() {
+        assert_eq!(print(Format::Json, false, vec![]), "");
+        assert_eq!(
+            print(Format::Json, false, vec![summary(1), summary(2)]),
+            "{\"summary\":true,\"n\":1,\"f\":0,\"i\":0,\"d\":0}\n\
+             {\"summary\":true,\"n\":2,\"f\":0,\"i\":0,\"d\":0}\n"
+        );
+    }
+
+    #[test]
+    fn printer_streams_json_arrays() {
+        assert_eq!(print(Format::Json, true, vec![]), "[]\n");
+        let printed = print(Format::Json, true, vec![summary(1), summary(2)]);
+        assert_eq!(printed.lines().count(), 2);
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&printed).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[1]["n"], 2);
+    }
+
+    #[test]
+    fn printer_buffers_pretty_arrays() {
+        assert_eq!(print(Format::JsonArray, false, vec![]), "[]\n");
+        let printed = print(Format::JsonArray, false, vec![summary(1), summary(2)]);
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&printed).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert!(printed.lines().count() > 2);
+    }
+
+    #[test]
+    fn printer_only_writes_commits_as_csv() {
+        assert_eq!(print(Format::Csv, false, vec![]), "a,b\n");
+        let mut printer = Printer::new(Vec::new(), Format::Csv, false);
+        assert!(printer.write(summary(1)).is_err());
+    }
+
+    #[test]
+    fn csv_leaves_plain_fields_alone() {
+        assert_eq!(csv_row(&["1", "a b", "", "é"]), "1,a b,,é");
+    }
+
+    #[test]
+    fn csv_quotes_delimiters_and_line_breaks() {
+        assert_eq!(csv_row(&["a,b", "c"]), "\"a,b\",c");
+        assert_eq!(csv_row(&["a\nb"]), "\"a\nb\"");
+        assert_eq!(csv_row(&["a\rb"]), "\"a\rb\"");
+        assert_eq!(csv_row(&["a\r\nb"]), "\"a\r\nb\"");
+    }
+
+    #[test]
+    fn csv_doubles_quotes() {
+        assert_eq!(csv_row(&["say \"hi\""]), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_row(&["\""]), "\"\"\"\"");
+    }
+
+    #[test]
+    fn csv_quotes_surrounding_whitespace() {
+        assert_eq!(
+            csv_row(&[" a", "b ", "\tc", "d"]),
+            "\" a\",\"b \",\"\tc\",d"
+        );
+    }
+}
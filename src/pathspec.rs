@@ -0,0 +1,225 @@
+//! Pathspecs are given relative to the current directory, and resolved to
+//! the top of the working tree before matching, as git does.
+//!
+//! libgit2 matches pathspecs like `fnmatch` without `FNM_PATHNAME`, so `*`
+//! crosses directories. `Globs` instead matches them as git's
+//! `--glob-pathspecs` does.
+
+use crate::exclude::exclusion;
+use git2::Error;
+use regex::{Regex, RegexBuilder};
+
+/// Resolve `spec`, given in the directory `prefix` of the working tree, to a
+/// pathspec from the top of it. Exclusions keep their magic, and `:/` marks a
+/// pathspec already from the top. With `literal`, nothing is magic.
+pub fn from_top(prefix: &str, spec: &str, literal: bool) -> Result<String, Error> {
+    let (magic, path, top) = match spec.strip_prefix(":/") {
+        _ if literal => ("", spec, false),
+        Some(path) => ("", path, true),
+        None => match exclusion(spec, false) {
+            Some(path) => (&spec[..spec.len() - path.len()], path, false),
+            None => ("", spec, false),
+        },
+    };
+    let mut parts: Vec<&str> = if top {
+        Vec::new()
+    } else {
+        prefix.split('/').filter(|part| !part.is_empty()).collect()
+    };
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                if parts.pop().is_none() {
+                    return Err(Error::from_str(&format!(
+                        "pathspec '{}' is outside the repository",
+                        spec
+                    )));
+                }
+            }
+            part => parts.push(part),
+        }
+    }
+    let mut resolved = parts.join("/");
+    if resolved.is_empty() {
+        resolved.push('.');
+    } else if path.ends_with('/') {
+        resolved.push('/');
+    }
+    Ok(format!("{}{}", magic, resolved))
+}
+
+/// Pathspecs matched with git's glob magic: `*` and `?` stop at `/`, `**/`
+/// matches any number of directories, and a pathspec without wildcards also
+/// matches everything beneath it.
+pub struct Globs {
+    regexes: Vec<Regex>,
+}
+
+impl Globs {
+    pub fn new(specs: &[String], ignore_case: bool) -> Result<Self, Error> {
+        let regexes = specs
+            .iter()
+            .map(|spec| {
+                RegexBuilder::new(&glob_regex(spec))
+                    .case_insensitive(ignore_case)
+                    .build()
+                    .map_err(|e| Error::from_str(&format!("invalid pathspec '{}': {}", spec, e)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { regexes })
+    }
+
+    /// Whether `path`, from the top of the working tree, matches any pathspec.
+    pub fn is_match(&self, path: &str) -> bool {
+        self.regexes.iter().any(|regex| regex.is_match(path))
+    }
+}
+
+/// Translate a glob pathspec into an anchored regex.
+fn glob_regex(spec: &str) -> String {
+    if spec == "." {
+        return String::from(".*");
+    }
+    if !spec.contains(&['*', '?', '[', '\\'][..]) {
+        let dir = spec.trim_end_matches('/');
+        return format!("^{}(?:/.*)?$", regex::escape(dir));
+    }
+    let mut regex = String::from("^");
+    let chars: Vec<char> = spec.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*')
+                && (i == 0 || chars[i - 1] == '/')
+                && matches!(chars.get(i + 2), None | Some('/')) =>
+            {
+                // `**/` is any number of directories, and a final `**` anything
+                if chars.get(i + 2).is_some() {
+                    regex.push_str("(?:.*/)?");
+                    i += 3;
+                } else {
+                    regex.push_str(".*");
+                    i += 2;
+                }
+                continue;
+            }
+            '*' => {
+                while chars.get(i + 1) == Some(&'*') {
+                    i += 1;
+                }
+                regex.push_str("[^/]*");
+            }
+            '?' => regex.push_str("[^/]"),
+            '[' => match chars[i + 1..].iter().skip(1).position(|&c| c == ']') {
+                Some(end) => {
+                    let class: String = chars[i + 1..i + 2 + end].iter().collect();
+                    let (negated, class) = match class.strip_prefix(&['!', '^'][..]) {
+                        Some(class) => (true, class),
+                        None => (false, &class[..]),
+                    };
+                    let class = class.replace('\\', "\\\\").replace('[', "\\[");
+                    regex.push_str(&format!("[{}{}]", if negated { "^/" } else { "" }, class));
+                    i += end + 3;
+                    continue;
+                }
+                None => regex.push_str("\\["),
+            },
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                regex.push_str(&regex::escape(&chars[i].to_string()));
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from(prefix: &str, spec: &str) -> String {
+        from_top(prefix, spec, false).unwrap()
+    }
+
+    #[test]
+    fn prefixes_pathspecs_with_the_current_directory() {
+        assert_eq!(from("", "main.rs"), "main.rs");
+        assert_eq!(from("src", "main.rs"), "src/main.rs");
+        assert_eq!(from("src/bin", "*.rs"), "src/bin/*.rs");
+        assert_eq!(from("src", "dir/"), "src/dir/");
+        assert_eq!(from("src", "./a//b"), "src/a/b");
+    }
+
+    #[test]
+    fn resolves_parent_directories() {
+        assert_eq!(from("src", "../README.md"), "README.md");
+        assert_eq!(from("src/bin", "../lib.rs"), "src/lib.rs");
+        assert_eq!(from("src", ".."), ".");
+        assert!(from_top("src", "../../x", false).is_err());
+        assert!(from_top("", "..", false).is_err());
+    }
+
+    #[test]
+    fn keeps_exclusion_magic() {
+        assert_eq!(from("src", ":!main.rs"), ":!src/main.rs");
+        assert_eq!(from("src", ":^main.rs"), ":^src/main.rs");
+        assert_eq!(from("src", ":(exclude)../x"), ":(exclude)x");
+    }
+
+    #[test]
+    fn top_magic_ignores_the_current_directory() {
+        assert_eq!(from("src", ":/README.md"), "README.md");
+        assert_eq!(from("src", ":/"), ".");
+    }
+
+    #[test]
+    fn literal_pathspecs_have_no_magic() {
+        assert_eq!(from_top("src", ":!x", true).unwrap(), "src/:!x");
+        assert_eq!(from_top("src", ":/x", true).unwrap(), "src/:/x");
+    }
+
+    #[test]
+    fn globs_stop_at_directories() {
+        let globs = |spec: &str| Globs::new(&[spec.to_owned()], false).unwrap();
+        let matches = |spec, paths: &[&str]| -> Vec<bool> {
+            let globs = globs(spec);
+            paths.iter().map(|path| globs.is_match(path)).collect()
+        };
+        let paths = ["a.rs", "src/b.rs", "src/bin/c.rs", "x.txt"];
+        assert_eq!(matches("*.rs", &paths), [true, false, false, false]);
+        assert_eq!(matches("src/*", &paths), [false, true, false, false]);
+        assert_eq!(matches("s*", &paths), [false, false, false, false]);
+        assert_eq!(matches("**/*.rs", &paths), [true, true, true, false]);
+        assert_eq!(matches("src/**", &paths), [false, true, true, false]);
+        assert_eq!(matches("src/**/*.rs", &paths), [false, true, true, false]);
+        assert_eq!(matches("**", &paths), [true, true, true, true]);
+        assert_eq!(matches("src/?.rs", &paths), [false, true, false, false]);
+        assert_eq!(matches("src/[ab].rs", &paths), [false, true, false, false]);
+        assert_eq!(matches("src/[!b].rs", &paths), [false, false, false, false]);
+        assert_eq!(matches("**/bin", &paths), [false, false, false, false]);
+    }
+
+    #[test]
+    fn globs_without_wildcards_match_directories() {
+        let globs = |spec: &str| Globs::new(&[spec.to_owned()], false).unwrap();
+        assert!(globs("src").is_match("src/bin/c.rs"));
+        assert!(globs("src/").is_match("src/b.rs"));
+        assert!(globs("src/b.rs").is_match("src/b.rs"));
+        assert!(!globs("sr").is_match("src/b.rs"));
+        assert!(globs(".").is_match("x.txt"));
+        assert!(globs("a\\*").is_match("a*"));
+        assert!(!globs("a\\*").is_match("ab"));
+        assert!(globs("a.(rs)").is_match("a.(rs)"));
+    }
+
+    #[test]
+    fn globs_can_ignore_case() {
+        let specs = ["SRC/*.RS".to_owned()];
+        assert!(!Globs::new(&specs, false).unwrap().is_match("src/b.rs"));
+        assert!(Globs::new(&specs, true).unwrap().is_match("src/b.rs"));
+    }
+}
@@ -0,0 +1,101 @@
+//! Walking shallow clones, whose history is cut off at a boundary.
+//!
+//! git records the commits at the boundary in `.git/shallow` and treats them
+//! as root commits, their parents grafted away. libgit2's revwalk knows
+//! nothing of this and fails on the first missing parent, so shallow clones
+//! are walked by hand instead.
+
+use git2::{Commit, Error, Oid, Repository};
+use std::collections::{BinaryHeap, HashSet};
+use std::fs;
+use std::io;
+
+/// Commits whose parents were cut off by a shallow clone. Empty for any
+/// other repository.
+#[derive(Clone, Debug, Default)]
+pub struct Grafts(HashSet<Oid>);
+
+impl Grafts {
+    /// Read the boundary commits from the repository's `shallow` file.
+    pub fn load(repo: &Repository) -> Result<Self, Error> {
+        let path = repo.path().join("shallow");
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(Error::from_str(&format!(
+                    "couldn't read {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        let ids = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(Oid::from_str)
+            .collect::<Result<_, _>>()?;
+        Ok(Self(ids))
+    }
+
+    /// Number of parents of `commit`, none if it's at the boundary.
+    pub fn parent_count(&self, commit: &Commit) -> usize {
+        if self.0.contains(&commit.id()) {
+            0
+        } else {
+            commit.parent_count()
+        }
+    }
+
+    /// Ids of the parents of `commit`, none if it's at the boundary.
+    pub fn parent_ids(&self, commit: &Commit) -> Vec<Oid> {
+        if self.0.contains(&commit.id()) {
+            Vec::new()
+        } else {
+            commit.parent_ids().collect()
+        }
+    }
+}
+
+/// The commits reachable from `tips` but not from `hidden`, newest first by
+/// commit time as git's default order is, without crossing the boundary.
+pub fn walk(
+    repo: &Repository,
+    tips: &[Oid],
+    hidden: &[Oid],
+    grafts: &Grafts,
+    first_parent: bool,
+) -> Result<Vec<Oid>, Error> {
+    let parents = |id: Oid| -> Result<Vec<Oid>, Error> {
+        let mut parents = grafts.parent_ids(&repo.find_commit(id)?);
+        if first_parent {
+            parents.truncate(1);
+        }
+        Ok(parents)
+    };
+    // Everything a hidden commit reaches is hidden, through any parent
+    let mut seen = HashSet::new();
+    let mut stack = hidden.to_vec();
+    while let Some(id) = stack.pop() {
+        if seen.insert(id) {
+            stack.extend(grafts.parent_ids(&repo.find_commit(id)?));
+        }
+    }
+    let mut queue = BinaryHeap::new();
+    for &id in tips {
+        if seen.insert(id) {
+            queue.push((repo.find_commit(id)?.time().seconds(), id));
+        }
+    }
+    let mut ids = Vec::new();
+    while let Some((_, id)) = queue.pop() {
+        ids.push(id);
+        for parent in parents(id)? {
+            if seen.insert(parent) {
+                queue.push((repo.find_commit(parent)?.time().seconds(), parent));
+            }
+        }
+    }
+    Ok(ids)
+}
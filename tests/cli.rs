@@ -0,0 +1,2861 @@
+//! Tests running the binary on small repositories built with git.
+
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{self, Command, Output, Stdio};
+
+/// A repository in a scratch directory, removed when dropped.
+struct Fixture {
+    dir: PathBuf,
+    commits: u32,
+}
+
+impl Fixture {
+    fn new(name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("shortstat-dump-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let fixture = Fixture { dir, commits: 0 };
+        fixture.git(&["init", "-q"]);
+        fixture
+    }
+
+    /// A clone of `source`, with extra `git clone` arguments.
+    fn clone(source: &Fixture, name: &str, args: &[&str]) -> Self {
+        let fixture = Fixture::new(name);
+        fs::remove_dir_all(&fixture.dir).unwrap();
+        let url = format!("file://{}", source.dir.display());
+        let dir = fixture.dir.to_str().unwrap();
+        source.git(&[&["clone", "-q"], args, &[&url, dir]].concat());
+        fixture
+    }
+
+    /// Run git in the repository, with a fixed identity and a clock a minute
+    /// on for every commit, so ids are the same every run.
+    fn git(&self, args: &[&str]) -> String {
+        let date = format!("{} +0000", 1_500_000_000 + 60 * self.commits);
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(&self.dir)
+            .env("HOME", &self.dir)
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_AUTHOR_NAME", "Alice")
+            .env("GIT_AUTHOR_EMAIL", "alice@example.com")
+            .env("GIT_AUTHOR_DATE", &date)
+            .env("GIT_COMMITTER_NAME", "Alice")
+            .env("GIT_COMMITTER_EMAIL", "alice@example.com")
+            .env("GIT_COMMITTER_DATE", &date)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    fn write(&self, path: &str, contents: &str) {
+        let path = self.dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    /// Write files and commit everything, returning the new commit's id.
+    fn commit(&mut self, message: &str, files: &[(&str, &str)]) -> String {
+        self.commit_by("Alice <alice@example.com>", message, files)
+    }
+
+    /// Commit as `commit` does, authored by `author`.
+    fn commit_by(&mut self, author: &str, message: &str, files: &[(&str, &str)]) -> String {
+        for (path, contents) in files {
+            self.write(path, contents);
+        }
+        self.git(&["add", "-A"]);
+        let author = format!("--author={}", author);
+        self.git(&["commit", "-q", "--allow-empty", &author, "-m", message]);
+        self.commits += 1;
+        self.git(&["rev-parse", "HEAD"]).trim().to_owned()
+    }
+
+    /// Merge `branch` into the current branch, always making a merge commit.
+    fn merge(&mut self, branch: &str) {
+        self.git(&["merge", "-q", "--no-ff", "-m", "merge", branch]);
+        self.commits += 1;
+    }
+
+    /// Run the binary in `dir`, relative to the repository.
+    fn run_in(&self, dir: &str, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_shortstat-dump"))
+            .args(args)
+            .current_dir(self.dir.join(dir))
+            .env("HOME", &self.dir)
+            .output()
+            .unwrap()
+    }
+
+    /// Run the binary in the repository.
+    fn run(&self, args: &[&str]) -> Output {
+        self.run_in(".", args)
+    }
+
+    /// Run the binary in the repository, writing `input` to its stdin.
+    fn run_with_input(&self, args: &[&str], input: &str) -> Output {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_shortstat-dump"))
+            .args(args)
+            .current_dir(&self.dir)
+            .env("HOME", &self.dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(input.as_bytes()).unwrap();
+        drop(stdin);
+        child.wait_with_output().unwrap()
+    }
+
+    /// The records written by a successful run, one per line.
+    fn records(&self, args: &[&str]) -> Vec<Value> {
+        records(&self.run(args))
+    }
+
+    /// Ids listed by `git rev-list` with `args`, one per commit.
+    fn rev_list(&self, args: &[&str]) -> Vec<String> {
+        let output = self.git(&[&["rev-list"], args].concat());
+        output.lines().map(String::from).collect()
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn records(output: &Output) -> Vec<Value> {
+    assert!(
+        output.status.success(),
+        "run failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+/// The string field `key` of every record.
+fn strings(records: &[Value], key: &str) -> Vec<String> {
+    records
+        .iter()
+        .map(|record| record[key].as_str().unwrap().to_owned())
+        .collect()
+}
+
+/// Three commits in a line.
+fn linear(name: &str) -> Fixture {
+    let mut fixture = Fixture::new(name);
+    fixture.commit("one", &[("a", "1\n")]);
+    fixture.commit("two", &[("a", "1\n2\n"), ("b", "1\n")]);
+    fixture.commit("three", &[("b", "")]);
+    fixture
+}
+
+#[test]
+fn ids_match_rev_list() {
+    let fixture = linear("with-id");
+    let records = fixture.records(&["--with-id"]);
+    assert_eq!(strings(&records, "c"), fixture.rev_list(&["HEAD"]));
+    let records = fixture.records(&[]);
+    assert_eq!(records.len(), 3);
+    assert!(records.iter().all(|record| record.get("c").is_none()));
+}
+
+#[test]
+fn authors_round_trip() {
+    let mut fixture = Fixture::new("with-author");
+    fixture.commit("one", &[("a", "1\n")]);
+    fixture.commit_by("Zoë Ng <zoe@example.com>", "two", &[("a", "2\n")]);
+    let records = fixture.records(&["--with-author"]);
+    assert_eq!(strings(&records, "an"), ["Zoë Ng", "Alice"]);
+    assert_eq!(
+        strings(&records, "ae"),
+        ["zoe@example.com", "alice@example.com"]
+    );
+}
+
+#[test]
+fn times_are_the_author_dates() {
+    let mut fixture = Fixture::new("with-time");
+    fixture.commit("one", &[("a", "1\n")]);
+    fixture.git(&[
+        "commit",
+        "-q",
+        "--allow-empty",
+        "--date=1000000000 -0530",
+        "-m",
+        "two",
+    ]);
+    let records = fixture.records(&["--with-time"]);
+    let times: Vec<_> = records.iter().map(|r| r["t"].as_i64().unwrap()).collect();
+    let offsets: Vec<_> = records.iter().map(|r| r["tz"].as_i64().unwrap()).collect();
+    assert_eq!(times, [1_000_000_000, 1_500_000_000]);
+    assert_eq!(offsets, [-330, 0]);
+    let formatted = |format| {
+        let records = fixture.records(&["--with-time", "--date-format", format]);
+        strings(&records, "t")
+    };
+    assert_eq!(
+        formatted("rfc3339"),
+        ["2001-09-08T20:16:40-05:30", "2017-07-14T02:40:00+00:00"]
+    );
+    let iso = fixture.git(&["log", "--date=iso", "--format=%ad"]);
+    assert_eq!(formatted("iso"), iso.lines().collect::<Vec<_>>());
+}
+
+/// Commits by Alice and Bob, with Bob's the middle two of four.
+fn two_authors(name: &str) -> Fixture {
+    let mut fixture = Fixture::new(name);
+    fixture.commit("one", &[("a", "1\n")]);
+    fixture.commit_by("Bob <bob@example.com>", "two", &[("b", "1\n")]);
+    fixture.commit_by("Bob <bob@example.com>", "three", &[("b", "2\n")]);
+    fixture.commit("four", &[("a", "2\n")]);
+    fixture
+}
+
+#[test]
+fn author_filters_by_name_or_email() {
+    let fixture = two_authors("author");
+    let ids = |args: &[&str]| {
+        let records = fixture.records(&[&["--with-id"], args].concat());
+        strings(&records, "c")
+    };
+    let bob = fixture.rev_list(&["--author=Bob", "HEAD"]);
+    assert_eq!(bob.len(), 2);
+    assert_eq!(ids(&["--author", "Bob"]), bob);
+    assert_eq!(ids(&["--author", "BOB@EXAMPLE"]), bob);
+    assert_eq!(ids(&["--author", "alice"]).len(), 2);
+    assert_eq!(ids(&["--author", "carol"]).len(), 0);
+    assert_eq!(ids(&[]).len(), 4);
+}
+
+#[test]
+fn committer_filters_apart_from_author() {
+    // Bob's commits are committed by Alice, as cherry-picks would be
+    let fixture = two_authors("committer");
+    let count = |args: &[&str]| fixture.records(&[&[], args].concat()).len();
+    assert_eq!(count(&["--committer", "Alice"]), 4);
+    assert_eq!(count(&["--committer", "bob"]), 0);
+    assert_eq!(count(&["--author", "Bob", "--committer", "alice@"]), 2);
+    assert_eq!(count(&["--author", "Bob", "--committer", "Bob"]), 0);
+}
+
+#[test]
+fn grep_matches_messages_as_regexes() {
+    let mut fixture = Fixture::new("grep");
+    fixture.commit("add the parser", &[("a", "1\n")]);
+    fixture.commit(
+        "fix a crash\n\nThe body mentions a needle.",
+        &[("a", "2\n")],
+    );
+    fixture.commit("tidy up", &[("a", "3\n")]);
+    fs::write(fixture.dir.join("message"), b"caf\xe9 bug\n").unwrap();
+    fixture.git(&["commit", "-q", "--allow-empty", "-F", "message"]);
+    let count = |pattern| fixture.records(&["--grep", pattern]).len();
+    assert_eq!(count("needle"), 1);
+    assert_eq!(count("parser"), 1);
+    assert_eq!(count("fix|bug"), 2);
+    assert_eq!(count("^tidy"), 1);
+    assert_eq!(count("absent"), 0);
+    let inverted = |pattern| fixture.records(&["--grep", pattern, "--invert-grep"]).len();
+    assert_eq!(inverted("needle"), 3);
+    assert_eq!(inverted("fix|bug"), 2);
+    assert_eq!(inverted("absent"), 4);
+    assert_eq!(fixture.run(&["--invert-grep"]).status.code(), Some(1));
+}
+
+/// A root commit, a branch of one commit merged back, and one commit after.
+fn merged(name: &str) -> Fixture {
+    let mut fixture = Fixture::new(name);
+    fixture.commit("root", &[("a", "1\n")]);
+    fixture.git(&["checkout", "-q", "-b", "side"]);
+    fixture.commit("side", &[("b", "1\n2\n")]);
+    fixture.git(&["checkout", "-q", "-"]);
+    fixture.commit("main", &[("a", "1\n2\n3\n")]);
+    fixture.merge("side");
+    fixture.commit("after", &[("c", "1\n")]);
+    fixture
+}
+
+#[test]
+fn every_commit_is_emitted_by_default() {
+    let fixture = merged("default");
+    assert_eq!(fixture.records(&[]).len(), 5);
+}
+
+#[test]
+fn no_merges_leaves_out_only_merge_commits() {
+    let fixture = merged("no-merges");
+    assert_eq!(fixture.records(&["--no-merges"]).len(), 4);
+    assert_eq!(fixture.records(&["--max-parents", "1"]).len(), 4);
+    assert_eq!(fixture.records(&["--max-parents", "0"]).len(), 1);
+    assert_eq!(fixture.records(&["--merges"]).len(), 1);
+}
+
+#[test]
+fn patch_is_accepted_and_ignored() {
+    let fixture = merged("patch");
+    let output = fixture.run(&["--patch"]);
+    assert_eq!(records(&output), fixture.records(&[]));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("deprecated"));
+    assert_eq!(records(&fixture.run(&["-p"])), fixture.records(&[]));
+}
+
+#[test]
+fn summary_totals_the_records_before_it() {
+    let fixture = merged("summary");
+    for args in &[&[][..], &["--skip", "1", "--max-count", "3"]] {
+        let mut records = fixture.records(&[&["--summary"], *args].concat());
+        let summary = records.pop().unwrap();
+        assert_eq!(summary["summary"], true);
+        assert_eq!(summary["n"], records.len());
+        for key in &["f", "i", "d"] {
+            let total: u64 = records.iter().map(|r| r[key].as_u64().unwrap()).sum();
+            assert_eq!(summary[key], total, "{}", key);
+        }
+        assert!(records.iter().all(|r| r.get("summary").is_none()));
+    }
+    assert_eq!(fixture.records(&["--skip", "1", "--summary"]).len(), 5);
+}
+
+/// Split CSV text into rows of fields, undoing RFC 4180 quoting.
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let (mut rows, mut row, mut field) = (Vec::new(), Vec::new(), String::new());
+    let (mut quoted, mut chars) = (false, text.chars().peekable());
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+    rows
+}
+
+#[test]
+fn csv_rows_match_the_json_records() {
+    let mut fixture = Fixture::new("csv");
+    fixture.commit("one", &[("a", "1\n")]);
+    fixture.commit_by("Ng, \"Zoë\" Z <zoe@example.com>", "two", &[("a", "2\n3\n")]);
+    let flags = ["--with-id", "--with-author", "--with-time"];
+    let output = fixture.run(&[&["--format", "csv"], &flags[..]].concat());
+    assert!(output.status.success());
+    let rows = parse_csv(&String::from_utf8(output.stdout).unwrap());
+    let header = [
+        "files_changed",
+        "insertions",
+        "deletions",
+        "id",
+        "author_name",
+        "author_email",
+        "time",
+        "offset_minutes",
+    ];
+    assert_eq!(rows[0], header);
+    let keys = ["f", "i", "d", "c", "an", "ae", "t", "tz"];
+    let records = fixture.records(&flags);
+    assert_eq!(rows.len(), records.len() + 1);
+    for (row, record) in rows[1..].iter().zip(&records) {
+        let values: Vec<_> = keys
+            .iter()
+            .map(|key| match record[key] {
+                Value::String(ref s) => s.clone(),
+                ref v => v.to_string(),
+            })
+            .collect();
+        assert_eq!(*row, values);
+    }
+    assert_eq!(rows[1][4], "Ng, \"Zoë\" Z");
+}
+
+#[test]
+fn json_array_parses_even_when_empty() {
+    let fixture = linear("json-array");
+    let array = |args: &[&str]| {
+        let output = fixture.run(&[&["--format", "json-array"], args].concat());
+        assert!(output.status.success());
+        serde_json::from_slice::<Vec<Value>>(&output.stdout).unwrap()
+    };
+    assert_eq!(array(&[]), fixture.records(&[]));
+    assert_eq!(array(&["--author", "nobody"]), Vec::<Value>::new());
+}
+
+/// A couple of hundred commits over a few files, with a merge every so often.
+fn busy(name: &str) -> Fixture {
+    let mut fixture = Fixture::new(name);
+    fixture.commit("root", &[("a", "1\n")]);
+    for i in 0..200 {
+        let lines: String = (0..i % 7).map(|n| format!("{}\n", n * i)).collect();
+        let path = format!("dir{}/file{}", i % 3, i % 5);
+        fixture.commit(&format!("commit {}", i), &[(&path, &lines)]);
+        if i % 25 == 24 {
+            fixture.git(&["checkout", "-q", "-b", &format!("branch{}", i)]);
+            fixture.commit("branch", &[("b", &format!("{}\n", i))]);
+            fixture.git(&["checkout", "-q", "-"]);
+            fixture.merge(&format!("branch{}", i));
+        }
+    }
+    fixture
+}
+
+#[test]
+fn parallel_output_is_identical_to_serial() {
+    let fixture = busy("jobs");
+    for args in &[
+        &["--with-id", "--with-author", "--with-time"][..],
+        &["--reverse", "--summary"],
+        &[
+            "--format",
+            "csv",
+            "--with-id",
+            "--skip",
+            "3",
+            "--max-count",
+            "20",
+        ],
+        &["--with-id", "HEAD", "--", "dir1"],
+    ] {
+        let serial = fixture.run(args);
+        assert!(serial.status.success());
+        assert!(!serial.stdout.is_empty());
+        for jobs in &["2", "3", "8"] {
+            let parallel = fixture.run(&[&["--jobs", jobs], *args].concat());
+            assert!(parallel.status.success());
+            assert_eq!(parallel.stdout, serial.stdout, "--jobs {} {:?}", jobs, args);
+        }
+    }
+}
+
+#[test]
+fn per_file_records_count_each_file() {
+    let mut fixture = Fixture::new("per-file");
+    fixture.commit("one", &[("a", "1\n2\n3\n"), ("b", "1\n2\n")]);
+    let files = [("a", "1\n3\n4\n5\n"), ("b", ""), ("img", "\0\x01\n")];
+    let id = fixture.commit("two", &files);
+    let records = fixture.records(&["--per-file", "--max-count", "1"]);
+    assert_eq!(strings(&records, "path"), ["a", "b", "img"]);
+    assert!(records.iter().all(|r| r["c"] == *id));
+    let counts: Vec<_> = records
+        .iter()
+        .map(|r| (r["i"].as_u64().unwrap(), r["d"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(counts, [(2, 1), (0, 2), (0, 0)]);
+    let binary: Vec<_> = records.iter().map(|r| r["bin"] == true).collect();
+    assert_eq!(binary, [false, false, true]);
+}
+
+#[test]
+fn find_renames_counts_only_the_edited_lines() {
+    let mut fixture = Fixture::new("renames");
+    let lines: String = (0..20).map(|n| format!("line {}\n", n)).collect();
+    fixture.commit("add", &[("old.txt", &lines)]);
+    fs::remove_file(fixture.dir.join("old.txt")).unwrap();
+    fixture.commit(
+        "rename",
+        &[("new.txt", &lines.replace("line 3\n", "three\n"))],
+    );
+    let changes = |args: &[&str]| {
+        let records = fixture.records(&[&["--max-count", "1"], args].concat());
+        let record = &records[0];
+        (
+            record["f"].as_u64().unwrap(),
+            record["i"].as_u64().unwrap() + record["d"].as_u64().unwrap(),
+        )
+    };
+    assert_eq!(changes(&[]), (2, 40));
+    assert_eq!(changes(&["--find-renames"]), (1, 2));
+    assert_eq!(changes(&["-M", "--rename-threshold", "99%"]), (2, 40));
+    assert_eq!(changes(&["--find-renames", "--no-renames"]), changes(&[]));
+    assert_eq!(changes(&["--no-renames", "-M"]), (2, 40));
+    let output = fixture.run(&["--no-renames", "--follow", "HEAD", "--", "new.txt"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn whitespace_only_changes_can_be_ignored() {
+    let mut fixture = Fixture::new("whitespace");
+    fixture.commit("add", &[("a", "fn main() {\n    run(1, 2);\n}\n")]);
+    fixture.commit("reformat", &[("a", "fn main()  {\n\trun(1,2);  \n}\n")]);
+    let lines = |args: &[&str]| {
+        let records = fixture.records(&[&["--max-count", "1"], args].concat());
+        (
+            records[0]["i"].as_u64().unwrap(),
+            records[0]["d"].as_u64().unwrap(),
+        )
+    };
+    assert_eq!(lines(&[]), (2, 2));
+    assert_eq!(lines(&["--ignore-whitespace"]), (0, 0));
+    // `1, 2` to `1,2` removes whitespace rather than changing its amount
+    assert_eq!(lines(&["--ignore-whitespace-change"]), (1, 1));
+    assert_eq!(lines(&["--ignore-whitespace-eol"]), (2, 2));
+}
+
+#[test]
+fn binary_files_are_counted_apart() {
+    let mut fixture = Fixture::new("binary-count");
+    let png = "\u{89}PNG\r\n\u{1a}\n\0\0\0\rIHDR\0";
+    fixture.commit("add", &[("logo.png", png), ("main.rs", "fn main() {}\n")]);
+    let records = fixture.records(&["--with-binary-count"]);
+    assert_eq!(records[0]["f"], 2);
+    assert_eq!(records[0]["i"], 1);
+    assert_eq!(records[0]["b"], 1);
+    assert!(fixture.records(&[])[0].get("b").is_none());
+}
+
+#[test]
+fn since_and_until_are_inclusive() {
+    // Commits at 02:40, 02:41 and 02:42 UTC on 2017-07-14
+    let fixture = linear("since-until");
+    let count = |args: &[&str]| fixture.records(args).len();
+    assert_eq!(count(&["--since", "2017-07-14T02:41:00Z"]), 2);
+    assert_eq!(count(&["--until", "2017-07-14T02:41:00Z"]), 2);
+    assert_eq!(count(&["--since", "2017-07-14T02:41:01Z"]), 1);
+    let window = [
+        "--since",
+        "2017-07-14T02:41:00",
+        "--until",
+        "2017-07-14T02:41:59",
+    ];
+    assert_eq!(count(&window), 1);
+    assert_eq!(count(&["--since", "2017-07-14T04:41:00+02:00"]), 2);
+    assert_eq!(count(&["--until", "2017-07-13T22:39:59-04:00"]), 0);
+    assert_eq!(count(&["--until", "2017-07-13"]), 0);
+    assert_eq!(count(&["--until", "2017-07-14"]), 3);
+    assert_eq!(count(&["--since", "1500000060"]), 2);
+}
+
+#[test]
+fn first_parent_collapses_branches_into_their_merge() {
+    let fixture = merged("first-parent");
+    let records = fixture.records(&["--first-parent", "--with-id"]);
+    assert_eq!(
+        strings(&records, "c"),
+        fixture.rev_list(&["--first-parent", "HEAD"])
+    );
+    assert_eq!(records.len(), 4);
+    // The merge brings in the side branch's two line file only
+    assert_eq!(records[1]["f"], 1);
+    assert_eq!(records[1]["i"], 2);
+    let lines: u64 = records.iter().map(|r| r["i"].as_u64().unwrap()).sum();
+    assert_eq!(lines, 1 + 2 + 2 + 1);
+}
+
+#[test]
+fn merge_diff_modes_pick_the_parent() {
+    let mut fixture = Fixture::new("merge-diff");
+    fixture.commit("root", &[("a", "1\n"), ("c", "1\n")]);
+    fixture.git(&["checkout", "-q", "-b", "side"]);
+    fixture.commit("side", &[("b", "1\n2\n"), ("c", "1\n2\n")]);
+    fixture.git(&["checkout", "-q", "-"]);
+    fixture.commit("main", &[("a", "1\n2\n3\n")]);
+    // An evil merge, editing c beyond what either side did
+    fixture.git(&["merge", "-q", "--no-ff", "--no-commit", "side"]);
+    fixture.commit("merge", &[("c", "1\n2\nevil\n")]);
+    let merge = |mode| {
+        let records = fixture.records(&["--merges", "--merge-diff", mode]);
+        assert_eq!(records.len(), 1);
+        records[0].clone()
+    };
+    let stat = |record: &Value| {
+        (
+            record["f"].clone(),
+            record["i"].clone(),
+            record["d"].clone(),
+        )
+    };
+    let first = merge("first-parent");
+    assert_eq!(stat(&first), (2.into(), 4.into(), 0.into()));
+    assert!(first.get("m").is_none());
+    assert_eq!(
+        stat(&merge("second-parent")),
+        (2.into(), 3.into(), 0.into())
+    );
+    let combined = merge("combined");
+    assert_eq!(stat(&combined), (1.into(), 2.into(), 0.into()));
+    assert_eq!(combined["m"], "combined");
+    assert_eq!(fixture.records(&["--merges"]), [first]);
+}
+
+#[test]
+fn streamed_json_array_parses_even_when_empty() {
+    let fixture = linear("streamed-array");
+    let array = |args: &[&str]| {
+        let output = fixture.run(&[&["--json-array"], args].concat());
+        assert!(output.status.success());
+        let text = String::from_utf8(output.stdout).unwrap();
+        serde_json::from_str::<Vec<Value>>(&text).unwrap()
+    };
+    assert_eq!(array(&["--with-id"]), fixture.records(&["--with-id"]));
+    assert_eq!(array(&["--author", "nobody"]), Vec::<Value>::new());
+    let with_summary = array(&["--summary"]);
+    assert_eq!(with_summary.len(), 4);
+    assert_eq!(with_summary[3]["summary"], true);
+}
+
+#[test]
+fn count_matches_the_records_emitted() {
+    let fixture = busy("count");
+    for args in &[
+        &[][..],
+        &["--no-merges"],
+        &["--skip", "5", "--max-count", "17"],
+        &["--author", "alice", "HEAD", "--", "dir2"],
+    ] {
+        let output = fixture.run(&[&["--count"], *args].concat());
+        assert!(output.status.success());
+        let count: usize = String::from_utf8(output.stdout)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        assert_eq!(count, fixture.records(args).len(), "{:?}", args);
+    }
+}
+
+#[test]
+fn errors_exit_non_zero_on_stderr() {
+    let fixture = linear("errors");
+    let not_a_repo = env::temp_dir().join(format!("shortstat-dump-{}-not-a-repo", process::id()));
+    fs::create_dir_all(&not_a_repo).unwrap();
+    let not_a_repo = not_a_repo.to_str().unwrap();
+    for args in &[
+        &["--git-dir", not_a_repo][..],
+        &["--grep", "("],
+        &["no-such-revision"],
+    ] {
+        let output = fixture.run(args);
+        assert_eq!(output.status.code(), Some(1), "{:?}", args);
+        assert!(output.stdout.is_empty());
+        assert!(String::from_utf8_lossy(&output.stderr).starts_with("error: "));
+    }
+    let output = fixture.run(&["--git-dir", not_a_repo]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not a git repository"));
+    fs::remove_dir_all(not_a_repo).unwrap();
+}
+
+#[test]
+fn templates_lay_out_each_record() {
+    let fixture = linear("template");
+    let template = "{id}\\t{author}\\t{date}\\t{insertions}\\t{deletions}";
+    let output = fixture.run(&["--template", template]);
+    assert!(output.status.success());
+    let ids = fixture.rev_list(&["HEAD"]);
+    let expected = format!(
+        "{}\tAlice\t1500000120\t0\t1\n{}\tAlice\t1500000060\t2\t0\n{}\tAlice\t1500000000\t1\t0\n",
+        ids[0], ids[1], ids[2]
+    );
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+
+    let output = fixture.run(&["--template", "{id} {nope}"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown placeholder {nope}"));
+}
+
+#[test]
+fn author_date_order_differs_from_commit_date_order() {
+    // As if the middle commit was rebased onto the first long after writing
+    let mut fixture = Fixture::new("author-date-order");
+    let first = fixture.commit("one", &[("a", "1\n")]);
+    fixture.write("a", "2\n");
+    fixture.git(&["commit", "-q", "-a", "--date=1400000000 +0000", "-m", "two"]);
+    fixture.commits += 1;
+    let second = fixture.git(&["rev-parse", "HEAD"]).trim().to_owned();
+    let third = fixture.commit("three", &[("a", "3\n")]);
+    let (first, second, third) = (first.as_str(), second.as_str(), third.as_str());
+    let ids = |args: &[&str]| strings(&fixture.records(&[&["--with-id"], args].concat()), "c");
+    assert_eq!(ids(&["--date-order"]), [third, second, first]);
+    assert_eq!(ids(&["--author-date-order"]), [third, first, second]);
+    assert_eq!(
+        ids(&["--author-date-order", "--reverse"]),
+        [second, first, third]
+    );
+}
+
+/// Decode one MessagePack value from the front of `bytes`, as far as records
+/// use the format.
+fn decode_msgpack(bytes: &mut &[u8]) -> Value {
+    fn take<'a>(bytes: &mut &'a [u8], n: usize) -> &'a [u8] {
+        let (taken, rest) = bytes.split_at(n);
+        *bytes = rest;
+        taken
+    }
+    fn uint(bytes: &mut &[u8], n: usize) -> u64 {
+        take(bytes, n)
+            .iter()
+            .fold(0, |acc, b| acc << 8 | u64::from(*b))
+    }
+    fn int(bytes: &mut &[u8], n: usize) -> i64 {
+        let shift = 64 - 8 * n;
+        ((uint(bytes, n) << shift) as i64) >> shift
+    }
+    fn string(bytes: &mut &[u8], len: usize) -> Value {
+        Value::from(String::from_utf8(take(bytes, len).to_vec()).unwrap())
+    }
+    fn map(bytes: &mut &[u8], len: usize) -> Value {
+        let entries = (0..len).map(|_| {
+            let key = decode_msgpack(bytes).as_str().unwrap().to_owned();
+            (key, decode_msgpack(bytes))
+        });
+        Value::Object(entries.collect())
+    }
+    fn array(bytes: &mut &[u8], len: usize) -> Value {
+        Value::Array((0..len).map(|_| decode_msgpack(bytes)).collect())
+    }
+    let marker = take(bytes, 1)[0];
+    match marker {
+        0x00..=0x7f => Value::from(marker),
+        0x80..=0x8f => map(bytes, usize::from(marker & 0x0f)),
+        0x90..=0x9f => array(bytes, usize::from(marker & 0x0f)),
+        0xa0..=0xbf => string(bytes, usize::from(marker & 0x1f)),
+        0xc0 => Value::Null,
+        0xc2 => Value::from(false),
+        0xc3 => Value::from(true),
+        0xcb => Value::from(f64::from_bits(uint(bytes, 8))),
+        0xcc..=0xcf => Value::from(uint(bytes, 1 << (marker - 0xcc))),
+        0xd0..=0xd3 => Value::from(int(bytes, 1 << (marker - 0xd0))),
+        0xd9..=0xdb => {
+            let len = uint(bytes, 1 << (marker - 0xd9));
+            string(bytes, len as usize)
+        }
+        0xdc | 0xdd => {
+            let len = uint(bytes, 2 << (marker - 0xdc));
+            array(bytes, len as usize)
+        }
+        0xde | 0xdf => {
+            let len = uint(bytes, 2 << (marker - 0xde));
+            map(bytes, len as usize)
+        }
+        0xe0..=0xff => Value::from(marker as i8),
+        _ => panic!("unexpected marker {:#x}", marker),
+    }
+}
+
+#[test]
+fn msgpack_round_trips_to_the_json_records() {
+    let mut fixture = Fixture::new("msgpack");
+    fixture.commit("one", &[("a", "1\n")]);
+    let lines: String = (0..300).map(|n| format!("{}\n", n)).collect();
+    fixture.commit_by("Zoë Ng <zoe@example.com>", "two", &[("a", &lines)]);
+    fixture.git(&[
+        "commit",
+        "-q",
+        "--allow-empty",
+        "--date=1000000000 -0530",
+        "-m",
+        "three",
+    ]);
+    let flags = ["--with-id", "--with-author", "--with-time"];
+    let output = fixture.run(&[&["--format", "msgpack"], &flags[..]].concat());
+    assert!(output.status.success());
+    let mut bytes = &output.stdout[..];
+    let mut decoded = Vec::new();
+    while !bytes.is_empty() {
+        decoded.push(decode_msgpack(&mut bytes));
+    }
+    assert_eq!(decoded, fixture.records(&flags));
+    assert_eq!(decoded[1]["i"], 299);
+    assert_eq!(decoded[0]["tz"], -330);
+}
+
+#[test]
+fn by_author_sums_each_authors_commits() {
+    let mut fixture = Fixture::new("by-author");
+    fixture.commit("one", &[("a", "1\n2\n3\n")]);
+    fixture.commit_by("Bob <bob@work.example>", "two", &[("b", "1\n")]);
+    fixture.commit_by("Bob <bob@example.com>", "three", &[("b", "1\n2\n")]);
+    fixture.commit("four", &[("a", "1\n")]);
+    let totals: Vec<_> = fixture
+        .records(&["--by-author"])
+        .into_iter()
+        .map(|r| {
+            let email = r["ae"].as_str().unwrap().to_owned();
+            let counts: Vec<_> = ["n", "f", "i", "d"]
+                .iter()
+                .map(|k| r[k].as_u64().unwrap())
+                .collect();
+            (email, counts)
+        })
+        .collect();
+    assert_eq!(
+        totals,
+        [
+            ("alice@example.com".to_owned(), vec![2, 2, 3, 2]),
+            ("bob@example.com".to_owned(), vec![1, 1, 1, 0]),
+            ("bob@work.example".to_owned(), vec![1, 1, 1, 0]),
+        ]
+    );
+}
+
+#[test]
+fn by_extension_buckets_each_file() {
+    let mut fixture = Fixture::new("by-extension");
+    fixture.commit(
+        "one",
+        &[
+            ("src/main.rs", "fn main() {}\n"),
+            ("src/lib.rs", "//! Lib\n\npub fn f() {}\n"),
+            ("README.md", "# Title\n"),
+            ("Makefile", "all:\n"),
+        ],
+    );
+    fixture.commit(
+        "two",
+        &[
+            ("src/lib.rs", "//! Lib\n"),
+            ("README.md", "# Title\n\nMore\n"),
+        ],
+    );
+    let totals: Vec<_> = fixture
+        .records(&["--by-extension"])
+        .into_iter()
+        .map(|r| {
+            let extension = r["extension"].as_str().unwrap().to_owned();
+            let counts: Vec<_> = ["f", "i", "d"]
+                .iter()
+                .map(|k| r[k].as_u64().unwrap())
+                .collect();
+            (extension, counts)
+        })
+        .collect();
+    assert_eq!(
+        totals,
+        [
+            ("rs".to_owned(), vec![3, 4, 2]),
+            ("md".to_owned(), vec![2, 3, 0]),
+            ("".to_owned(), vec![1, 1, 0]),
+        ]
+    );
+}
+
+#[test]
+fn the_repository_is_found_from_a_subdirectory() {
+    let mut fixture = Fixture::new("discover");
+    fixture.commit("one", &[("src/deep/a", "1\n")]);
+    fixture.commit("two", &[("b", "1\n")]);
+    let from_top = fixture.records(&["--with-id"]);
+    assert_eq!(
+        records(&fixture.run_in("src/deep", &["--with-id"])),
+        from_top
+    );
+    // An explicit --git-dir isn't searched upwards from
+    let output = fixture.run(&["--git-dir", "src"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not a git repository: src"));
+}
+
+#[test]
+fn pathspecs_are_relative_to_the_current_directory() {
+    let mut fixture = Fixture::new("subdir");
+    fixture.commit("readme", &[("README.md", "hi\n"), ("main.rs", "top\n")]);
+    fixture.commit("main", &[("src/main.rs", "fn main() {}\n")]);
+    fixture.commit("lib", &[("src/lib.rs", "\n"), ("src/main.rs", "\n")]);
+    let count = |dir, args: &[&str]| {
+        let output = fixture.run_in(dir, &[&["--count", "HEAD", "--"], args].concat());
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    };
+    assert_eq!(count("src", &["main.rs"]), "2");
+    assert_eq!(count(".", &["main.rs"]), "1");
+    assert_eq!(count("src", &["../README.md"]), "1");
+    assert_eq!(count("src", &[":/main.rs"]), "1");
+    assert_eq!(count("src", &["*.rs"]), "2");
+    assert!(!fixture
+        .run_in("src", &["HEAD", "--", "../../x"])
+        .status
+        .success());
+}
+
+#[test]
+fn excluded_pathspecs_leave_paths_out() {
+    let mut fixture = Fixture::new("exclude");
+    fixture.commit("odd", &[(":!src", "1\n")]);
+    fixture.commit("lib", &[("src/lib.rs", "1\n2\n")]);
+    fixture.commit("gen", &[("src/gen.rs", "1\n2\n3\n")]);
+    fixture.commit("both", &[("src/lib.rs", "1\n"), ("src/gen.rs", "1\n")]);
+    fixture.commit("docs", &[("README.md", "hi\n")]);
+    let stats = |args: &[&str]| -> Vec<(String, u64, u64)> {
+        let args = [&["--with-subject", "--"], args].concat();
+        fixture
+            .records(&args)
+            .iter()
+            .map(|r| {
+                let subject = r["s"].as_str().unwrap().to_owned();
+                (subject, r["i"].as_u64().unwrap(), r["d"].as_u64().unwrap())
+            })
+            .collect()
+    };
+    let expected = [("both".to_owned(), 0, 1), ("lib".to_owned(), 2, 0)];
+    assert_eq!(stats(&["src/", ":!src/gen.rs"]), expected);
+    assert_eq!(stats(&["src/", ":^src/gen.rs"]), expected);
+    assert_eq!(stats(&["src/", ":(exclude)src/gen.rs"]), expected);
+    let subjects = |args: &[&str]| -> Vec<String> {
+        stats(args)
+            .into_iter()
+            .map(|(subject, ..)| subject)
+            .collect()
+    };
+    // Only exclusions leave out paths from everything
+    assert_eq!(subjects(&[":!src/"]), ["docs", "odd"]);
+    let excluded = fixture.rev_list(&["HEAD", "--", "src/", ":!src/gen.rs"]);
+    let records = fixture.records(&["--with-id", "--", "src/", ":!src/gen.rs"]);
+    assert_eq!(strings(&records, "c"), excluded);
+    // Nothing is magic to a literal pathspec
+    assert_eq!(subjects(&[":!src"]), ["docs", "odd"]);
+    let literal = fixture.records(&["--with-subject", "--literal-pathspecs", "--", ":!src"]);
+    assert_eq!(literal.len(), 1);
+    assert_eq!(literal[0]["s"], "odd");
+}
+
+#[test]
+fn revisions_and_pathspecs_are_split_at_the_separator() {
+    let mut fixture = Fixture::new("separator");
+    fixture.commit("base", &[("a", "1\n")]);
+    fixture.git(&["checkout", "-q", "-b", "side"]);
+    fixture.commit("side", &[("b", "1\n")]);
+    fixture.commit("both", &[("a", "2\n"), ("b", "2\n")]);
+    let subjects =
+        |args: &[&str]| strings(&fixture.records(&[&["--with-subject"], args].concat()), "s");
+    assert_eq!(subjects(&["side", "^side~2"]), ["both", "side"]);
+    assert_eq!(subjects(&["side", "^side~2", "--", "a"]), ["both"]);
+    assert_eq!(subjects(&["--", "a", "b"]), ["both", "side", "base"]);
+}
+
+#[test]
+fn dot_pathspecs_match_everything() {
+    let mut fixture = Fixture::new("dot");
+    fixture.commit("x", &[("x", "1\n")]);
+    fixture.commit("src", &[("src/a", "1\n")]);
+    fixture.commit("both", &[("x", "2\n"), ("src/b", "1\n")]);
+    let count = |dir, args: &[&str]| {
+        let output = fixture.run_in(dir, &[&["--count", "--"], args].concat());
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    };
+    assert_eq!(count(".", &["."]), "3");
+    assert_eq!(count(".", &["./"]), "3");
+    assert_eq!(count(".", &[".", "src"]), "3");
+    assert_eq!(count(".", &[".", ":!x"]), "2");
+    assert_eq!(count(".", &["./", ":!src"]), "2");
+    assert_eq!(count("src", &[".."]), "3");
+    assert_eq!(count("src", &["."]), "2");
+    let records = fixture.records(&["--", ".", ":!x"]);
+    let insertions: Vec<_> = records.iter().map(|r| r["i"].as_u64().unwrap()).collect();
+    assert_eq!(insertions, [1, 1]);
+}
+
+#[test]
+fn glob_pathspecs_match_as_git_does() {
+    let mut fixture = Fixture::new("glob-pathspecs");
+    fixture.commit(
+        "top",
+        &[("main.rs", "fn main() {}\n"), ("README.md", "hi\n")],
+    );
+    fixture.commit("lib", &[("src/lib.rs", "\n")]);
+    fixture.commit("bin", &[("src/bin/tool.rs", "\n")]);
+    fixture.commit("docs", &[("docs/Guide.MD", "\n")]);
+    let ids = |args: &[&str]| {
+        let args = [&["--with-id", "--pathspec-glob"], args].concat();
+        strings(&fixture.records(&args), "c")
+    };
+    let git = |spec| {
+        let output = fixture.git(&["--glob-pathspecs", "rev-list", "HEAD", "--", spec]);
+        output.lines().map(String::from).collect::<Vec<_>>()
+    };
+    for spec in &["**/*.rs", "src/*.rs", "*.rs", "src/**", "docs"] {
+        assert_eq!(ids(&["HEAD", "--", spec]), git(spec), "{}", spec);
+    }
+    assert_eq!(ids(&["HEAD", "--", "**/*.rs"]).len(), 3);
+    assert_eq!(ids(&["HEAD", "--", "src/*.rs"]).len(), 1);
+    // Exclusions are globs too, so `src/*` leaves `src/bin/tool.rs` in
+    let excluded = ids(&["--", "**/*.rs", ":!src/*"]);
+    let output = fixture.git(&[
+        "--glob-pathspecs",
+        "rev-list",
+        "HEAD",
+        "--",
+        "**/*.rs",
+        ":!src/*",
+    ]);
+    assert_eq!(excluded, output.lines().collect::<Vec<_>>());
+    assert_eq!(excluded.len(), 2);
+    // Only the matching files are counted
+    let records = fixture.records(&["--pathspec-glob", "HEAD", "--", "*.rs"]);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["f"], 1);
+    // Without the flag, * matches across directories
+    let count = fixture.records(&["HEAD", "--", "*.rs"]).len();
+    assert_eq!(count, 3);
+    let icase = ["--pathspec-icase", "HEAD", "--", "**/*.md"];
+    assert_eq!(ids(&icase).len(), 2);
+    let output = fixture.run(&["--pathspec-glob", "--literal-pathspecs"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn revs_from_walks_as_the_same_arguments_would() {
+    let fixture = merged("revs-from");
+    fixture.git(&["tag", "base", "HEAD~2"]);
+    let args = ["--with-id", "base..side"];
+    let expected = fixture.records(&args);
+    assert_eq!(expected.len(), 1);
+    let piped = fixture.run_with_input(&["--with-id", "--revs-from", "-"], "side\n^base\n\n");
+    assert_eq!(records(&piped), expected);
+    fixture.write("revs", "HEAD\n  ^side  \n");
+    let from_file = fixture.records(&["--with-id", "--revs-from", "revs"]);
+    assert_eq!(from_file, fixture.records(&["--with-id", "side..HEAD"]));
+    // Revisions on the command line are walked too
+    let both = fixture.run_with_input(&["--with-id", "--revs-from", "-", "side"], "^base\n");
+    assert_eq!(records(&both), expected);
+    let output = fixture.run(&["--revs-from", "absent"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn follow_tracks_a_file_back_through_its_rename() {
+    let mut fixture = Fixture::new("follow");
+    let text = "one\ntwo\nthree\nfour\nfive\nsix\n";
+    fixture.commit("add", &[("old.txt", text)]);
+    fixture.commit("edit", &[("old.txt", &format!("{}seven\n", text))]);
+    fixture.commit("other", &[("other.txt", "1\n")]);
+    fixture.git(&["mv", "old.txt", "new.txt"]);
+    fixture.commit("rename", &[("new.txt", &format!("{}seven\neight\n", text))]);
+    fixture.commit("after", &[("new.txt", "")]);
+    let followed = fixture.git(&["log", "--follow", "--format=%H", "--", "new.txt"]);
+    let records = fixture.records(&["--with-id", "--follow", "HEAD", "--", "new.txt"]);
+    assert_eq!(strings(&records, "c"), followed.lines().collect::<Vec<_>>());
+    assert_eq!(records.len(), 4);
+    // The rename counts only its edited line
+    assert_eq!(
+        (records[1]["i"].as_u64(), records[1]["d"].as_u64()),
+        (Some(1), Some(0))
+    );
+    // Without --follow, history stops at the rename
+    let unfollowed = fixture.records(&["HEAD", "--", "new.txt"]);
+    assert_eq!(unfollowed.len(), 2);
+}
+
+#[test]
+fn diff_algorithms_can_count_lines_differently() {
+    let mut fixture = Fixture::new("diff-algorithm");
+    fixture.commit("one", &[("f", "b\ny\na\nc\na\nx\nx\nx\n")]);
+    fixture.commit("two", &[("f", "x\nb\na\nx\na\nx\nx\ny\n")]);
+    let counts = |algorithm| {
+        let records = fixture.records(&["--diff-algorithm", algorithm]);
+        (
+            records[0]["i"].as_u64().unwrap(),
+            records[0]["d"].as_u64().unwrap(),
+        )
+    };
+    let git = |algorithm| {
+        let algorithm = format!("--diff-algorithm={}", algorithm);
+        fixture.git(&["show", "--shortstat", "--format=", &algorithm, "HEAD"])
+    };
+    assert_eq!(counts("myers"), (3, 3));
+    assert!(git("myers").contains("3 insertions(+), 3 deletions(-)"));
+    assert_eq!(counts("patience"), (6, 6));
+    assert!(git("patience").contains("6 insertions(+), 6 deletions(-)"));
+    assert_eq!(counts("minimal"), (3, 3));
+    let output = fixture.run(&["--diff-algorithm", "histogram"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn context_lines_leave_the_counts_alone() {
+    let mut fixture = Fixture::new("context");
+    let lines: Vec<_> = (0..20).map(|n| n.to_string()).collect();
+    fixture.commit("one", &[("f", &(lines.join("\n") + "\n"))]);
+    let mut edited = lines.clone();
+    edited[2] = "two".to_owned();
+    edited[15] = "fifteen".to_owned();
+    edited.insert(8, "new".to_owned());
+    fixture.commit("two", &[("f", &(edited.join("\n") + "\n"))]);
+    let default = fixture.records(&["--per-file"]);
+    assert_eq!(
+        (default[0]["i"].as_u64(), default[0]["d"].as_u64()),
+        (Some(3), Some(2))
+    );
+    for args in &[
+        ["-U", "0"],
+        ["--context", "100"],
+        ["--inter-hunk-context", "0"],
+        ["--inter-hunk-context", "50"],
+    ] {
+        let args = [&["--per-file"], &args[..]].concat();
+        assert_eq!(fixture.records(&args), default, "{:?}", args);
+    }
+}
+
+#[test]
+fn numbers_follow_the_output_order() {
+    let fixture = linear("number");
+    let numbered = |args: &[&str]| {
+        let records = fixture.records(&[&["--with-id", "--number"], args].concat());
+        let numbers: Vec<_> = records
+            .iter()
+            .map(|r| r["number"].as_u64().unwrap())
+            .collect();
+        (strings(&records, "c"), numbers)
+    };
+    let (ids, numbers) = numbered(&[]);
+    assert_eq!(numbers, [0, 1, 2]);
+    let (reversed, numbers) = numbered(&["--reverse"]);
+    assert_eq!(numbers, [0, 1, 2]);
+    assert_eq!(reversed, ids.iter().rev().cloned().collect::<Vec<_>>());
+    let (skipped, numbers) = numbered(&["--skip", "1"]);
+    assert_eq!(numbers, [0, 1]);
+    assert_eq!(skipped, ids[1..]);
+    let output = fixture.run(&["--number", "--format", "csv"]);
+    let header = String::from_utf8(output.stdout).unwrap();
+    assert!(header.starts_with("files_changed,insertions,deletions,number\n"));
+    assert!(fixture
+        .records(&[])
+        .iter()
+        .all(|r| r.get("number").is_none()));
+}
+
+#[test]
+fn parents_list_each_commits_parent_ids() {
+    let fixture = merged("with-parents");
+    let records = fixture.records(&["--with-id", "--with-parents", "--topo-order"]);
+    let parents: Vec<Vec<String>> = records
+        .iter()
+        .map(|record| {
+            let parents = record["p"].as_array().unwrap();
+            parents
+                .iter()
+                .map(|p| p.as_str().unwrap().to_owned())
+                .collect()
+        })
+        .collect();
+    let lengths: Vec<_> = parents.iter().map(Vec::len).collect();
+    assert_eq!(lengths, [1, 2, 1, 1, 0]);
+    let git = fixture.git(&["log", "--topo-order", "--format=%P"]);
+    let expected: Vec<Vec<String>> = git
+        .lines()
+        .map(|line| line.split_whitespace().map(String::from).collect())
+        .collect();
+    assert_eq!(parents, expected);
+    let output = fixture.run(&["--with-parents", "--format", "csv", "HEAD~1"]);
+    let csv = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(csv.lines().nth(1).unwrap().matches(' ').count(), 1);
+}
+
+#[test]
+fn stat_limits_drop_commits_once_diffed() {
+    let mut fixture = Fixture::new("stat-limits");
+    let vendored: String = (0..500).map(|n| format!("{}\n", n)).collect();
+    fixture.commit("small", &[("a", "1\n2\n")]);
+    fixture.commit("vendor", &[("vendor/lib", &vendored)]);
+    fixture.commit("tidy", &[("a", "1\n")]);
+    let inserted = |args: &[&str]| {
+        let records = fixture.records(args);
+        records
+            .iter()
+            .map(|r| r["i"].as_u64().unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(inserted(&["--max-insertions", "499"]), [0, 2]);
+    assert_eq!(inserted(&["--max-insertions", "500"]), [0, 500, 2]);
+    assert_eq!(inserted(&["--min-insertions", "1"]), [500, 2]);
+    assert_eq!(inserted(&["--min-deletions", "1"]), [0]);
+    assert_eq!(inserted(&["--max-deletions", "0"]), [500, 2]);
+    // Skipping and limiting count only the commits kept
+    assert_eq!(inserted(&["--max-insertions", "499", "--skip", "1"]), [2]);
+    assert_eq!(inserted(&["--min-insertions", "1", "-n", "1"]), [500]);
+    let output = fixture.run(&["--count", "--max-insertions", "499"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\n");
+}
+
+#[test]
+fn subjects_are_the_first_line_of_each_message() {
+    let mut fixture = Fixture::new("with-subject");
+    fixture.commit(
+        "Fix the parser  \n\nIt crashed on empty input.",
+        &[("a", "1\n")],
+    );
+    fixture.git(&[
+        "commit",
+        "-q",
+        "--allow-empty",
+        "--allow-empty-message",
+        "-m",
+        "",
+    ]);
+    fixture.commit("Añadir más", &[("a", "2\n")]);
+    let subjects = |args: &[&str]| {
+        let records = fixture.records(&[&["--with-subject"], args].concat());
+        strings(&records, "s")
+    };
+    assert_eq!(subjects(&[]), ["Añadir más", "", "Fix the parser"]);
+    assert_eq!(subjects(&["--subject-maxlen", "5"]), ["Añadi", "", "Fix t"]);
+    assert!(fixture.records(&[]).iter().all(|r| r.get("s").is_none()));
+}
+
+#[test]
+fn decorate_names_the_refs_on_each_commit() {
+    let fixture = linear("decorate");
+    fixture.git(&["tag", "v1", "HEAD~1"]);
+    fixture.git(&["tag", "-a", "-m", "release", "v2", "HEAD~1"]);
+    fixture.git(&["branch", "topic", "HEAD"]);
+    let records = fixture.records(&["--decorate"]);
+    let refs: Vec<_> = records.iter().map(|r| r.get("refs").cloned()).collect();
+    let branch = fixture.git(&["symbolic-ref", "--short", "HEAD"]);
+    assert_eq!(
+        refs,
+        [
+            Some(serde_json::json!(["HEAD", branch.trim(), "topic"])),
+            Some(serde_json::json!(["v1", "v2"])),
+            None,
+        ]
+    );
+}
+
+#[test]
+fn bare_clones_give_the_same_records() {
+    let fixture = busy("non-bare");
+    let bare = Fixture::clone(&fixture, "bare", &["--bare"]);
+    assert_eq!(bare.git(&["rev-parse", "--is-bare-repository"]), "true\n");
+    let git_dir = bare.dir.to_str().unwrap();
+    for args in &[
+        &["--with-id", "--with-author", "--with-time", "--decorate"][..],
+        &["--per-file", "--find-renames"],
+        &["--summary", "HEAD", "--", "dir1"],
+    ] {
+        let expected = fixture.records(args);
+        assert_eq!(bare.records(args), expected, "{:?}", args);
+        let args = [&["--git-dir", git_dir], *args].concat();
+        assert_eq!(records(&fixture.run(&args)), expected, "{:?}", args);
+    }
+    // Run from inside a bare repository, pathspecs can't be relative to a
+    // directory in the tree, so are from its top
+    let from_refs = bare.run_in("refs", &["--count", "HEAD", "--", "dir1"]);
+    let count = fixture.run(&["--count", "HEAD", "--", "dir1"]);
+    assert!(from_refs.status.success());
+    assert_eq!(from_refs.stdout, count.stdout);
+}
+
+#[test]
+fn progress_never_touches_stdout() {
+    let fixture = busy("progress");
+    let plain = fixture.run(&["--with-id"]);
+    let total = fixture.rev_list(&["HEAD"]).len();
+    for flag in &["--progress", "--progress-always"] {
+        let output = fixture.run(&["--with-id", flag]);
+        assert!(output.status.success());
+        assert_eq!(output.stdout, plain.stdout, "{}", flag);
+    }
+    // Progress is only written to a terminal unless forced
+    let stderr = |flag| String::from_utf8(fixture.run(&[flag]).stderr).unwrap();
+    assert_eq!(stderr("--progress"), "");
+    let forced = stderr("--progress-always");
+    assert!(forced.starts_with("\rprocessed 100 commits\rprocessed 200 commits"));
+    assert!(forced.ends_with(&format!("\rprocessed {} commits\n", total)));
+}
+
+#[test]
+fn abbreviated_ids_stay_unique() {
+    let fixture = busy("abbrev");
+    let ids = |abbrev| strings(&fixture.records(&["--with-id", "--abbrev", abbrev]), "c");
+    let full = strings(&fixture.records(&["--with-id"]), "c");
+    let short = ids("7");
+    assert!(short.iter().all(|id| id.len() == 7));
+    assert!(short
+        .iter()
+        .zip(&full)
+        .all(|(id, full)| full.starts_with(id.as_str())));
+    // With only four digits some ids collide, and are lengthened as git does
+    let shortest = ids("4");
+    let git = fixture.git(&["log", "--abbrev=4", "--format=%h"]);
+    assert_eq!(shortest, git.lines().collect::<Vec<_>>());
+    assert!(shortest.iter().any(|id| id.len() > 4));
+}
+
+#[test]
+fn skip_and_max_count_apply_after_filtering() {
+    let mut fixture = Fixture::new("skip-max-count");
+    for i in 0..8 {
+        let author = if i % 3 == 0 {
+            "Bob <bob@example.com>"
+        } else {
+            "Alice <alice@example.com>"
+        };
+        let path = if i % 2 == 0 { "src/a" } else { "b" };
+        fixture.commit_by(
+            author,
+            &format!("commit {}", i),
+            &[(path, &format!("{}\n", i))],
+        );
+    }
+    let ids = |args: &[&str]| strings(&fixture.records(&[&["--with-id"], args].concat()), "c");
+    for (args, git) in &[
+        (&["--skip", "2"][..], &["--skip=2"][..]),
+        (&["--max-count", "3"], &["--max-count=3"]),
+        (&["--skip", "1", "-n", "2"], &["--skip=1", "--max-count=2"]),
+        (
+            &["--skip", "2", "--author", "alice"],
+            &["--skip=2", "--author=alice"],
+        ),
+        (
+            &["--skip", "1", "-n", "1", "--author", "Bob"],
+            &["--skip=1", "-n1", "--author=Bob"],
+        ),
+        (
+            &["--skip", "1", "HEAD", "--", "src"],
+            &["--skip=1", "HEAD", "--", "src"],
+        ),
+        (&["--skip", "10"], &["--skip=10"]),
+    ] {
+        let git = if git.contains(&"--") {
+            git.to_vec()
+        } else {
+            [*git, &["HEAD"]].concat()
+        };
+        assert_eq!(ids(args), fixture.rev_list(&git), "{:?}", args);
+    }
+    let alice = fixture.rev_list(&["--author=alice", "HEAD"]);
+    assert_eq!(ids(&["--skip", "2", "--author", "alice"]), alice[2..]);
+    let output = fixture.run(&["--max-count", "0"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let output = fixture.run(&["--count", "--max-count", "0"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "0\n");
+}
+
+#[test]
+fn net_is_insertions_less_deletions() {
+    let fixture = linear("verify-net");
+    let records = fixture.records(&["--verify-net"]);
+    for record in &records {
+        let (i, d) = (record["i"].as_i64().unwrap(), record["d"].as_i64().unwrap());
+        assert_eq!(record["net"].as_i64(), Some(i - d));
+    }
+    let nets: Vec<_> = records.iter().map(|r| r["net"].as_i64().unwrap()).collect();
+    assert_eq!(nets, [-1, 2, 1]);
+    assert!(fixture.records(&[]).iter().all(|r| r.get("net").is_none()));
+}
+
+#[test]
+fn null_separates_records() {
+    let fixture = linear("null");
+    let split = |args: &[&str]| {
+        let output = fixture.run(args);
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(!stdout.ends_with('\n') && !stdout.ends_with('\0'));
+        stdout.split('\0').map(String::from).collect::<Vec<_>>()
+    };
+    let records = split(&["-z", "--with-id"]);
+    assert_eq!(records.len(), 3);
+    let parsed: Vec<Value> = records
+        .iter()
+        .map(|r| serde_json::from_str(r).unwrap())
+        .collect();
+    assert_eq!(parsed, fixture.records(&["--with-id"]));
+    assert_eq!(split(&["--null", "--format", "csv"]).len(), 4);
+    assert_eq!(split(&["-z", "--template", "{id}"]).len(), 3);
+    assert_eq!(
+        fixture.run(&["-z", "--format", "json-array"]).status.code(),
+        Some(1)
+    );
+}
+
+#[test]
+fn path_regex_keeps_commits_changing_a_matching_path() {
+    let mut fixture = Fixture::new("path-regex");
+    let lib = fixture.commit("lib", &[("src/lib.rs", "\n")]);
+    let test = fixture.commit("test", &[("src/lib_test.rs", "\n")]);
+    fixture.commit("docs", &[("docs/test.md", "\n")]);
+    let both = fixture.commit(
+        "both",
+        &[("src/lib.rs", "1\n"), ("tests/cli_test.rs", "\n")],
+    );
+    let ids = |args: &[&str]| strings(&fixture.records(&[&["--with-id"], args].concat()), "c");
+    assert_eq!(ids(&["--path-regex", r"_test\.rs$"]), [both.clone(), test]);
+    assert_eq!(
+        ids(&["--path-regex", r"^src/lib\.rs$"]),
+        [both.clone(), lib]
+    );
+    // Positional pathspecs must match as well
+    assert_eq!(
+        ids(&["--path-regex", r"_test\.rs$", "HEAD", "--", "tests"]),
+        [both]
+    );
+    assert_eq!(fixture.run(&["--path-regex", "("]).status.code(), Some(1));
+}
+
+#[test]
+fn output_files_match_stdout() {
+    let fixture = merged("output");
+    let path = fixture.dir.join("shortstats.out");
+    let path = path.to_str().unwrap();
+    for args in &[
+        &["--with-id", "--summary"][..],
+        &["--format", "csv", "--with-author"],
+        &["--format", "msgpack"],
+        &["--count"],
+    ] {
+        let stdout = fixture.run(args);
+        let written = fixture.run(&[&["-o", path], *args].concat());
+        assert!(written.status.success());
+        assert!(written.stdout.is_empty());
+        assert_eq!(fs::read(path).unwrap(), stdout.stdout, "{:?}", args);
+    }
+    let output = fixture.run(&["--output", "absent/dir/out"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("couldn't create absent/dir/out"));
+}
+
+#[test]
+fn shallow_boundary_commits_are_diffed_as_roots() {
+    let mut source = merged("shallow-source");
+    source.git(&["checkout", "-q", "-b", "other", "HEAD~1"]);
+    source.commit("other", &[("d", "1\n2\n3\n4\n")]);
+    source.git(&["checkout", "-q", "-"]);
+    let insertions = |records: &[Value]| -> Vec<u64> {
+        let insertions = records.iter().map(|r| r["i"].as_u64().unwrap());
+        insertions.collect()
+    };
+
+    let clone = Fixture::clone(&source, "shallow-1", &["--depth", "1"]);
+    let output = clone.run(&[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--unshallow"));
+    let shallow = clone.records(&["--allow-shallow"]);
+    assert_eq!(insertions(&shallow), [6]);
+
+    let clone = Fixture::clone(&source, "shallow-2", &["--depth", "2"]);
+    let shallow = clone.records(&["--allow-shallow", "--with-parents"]);
+    assert_eq!(insertions(&shallow), [1, 5]);
+    assert_eq!(shallow[1]["p"], serde_json::json!([]));
+
+    // A boundary on one branch doesn't stop the walk down the others
+    let clone = Fixture::clone(
+        &source,
+        "shallow-branches",
+        &["--depth", "2", "--no-single-branch"],
+    );
+    let args = ["--allow-shallow", "--revs-from", "-"];
+    let output = clone.run_with_input(&args, "HEAD\norigin/other\norigin/side\n");
+    assert_eq!(insertions(&records(&output)), [4, 1, 5, 2, 1]);
+}
+
+#[test]
+fn skip_empty_drops_commits_that_change_nothing() {
+    let mut fixture = Fixture::new("skip-empty");
+    let one = fixture.commit("one", &[("a", "1\n")]);
+    let empty = fixture.commit("empty", &[]);
+    let two = fixture.commit("two", &[("a", "2\n")]);
+    let ids = |args: &[&str]| strings(&fixture.records(&[&["--with-id"], args].concat()), "c");
+    assert_eq!(ids(&[]), [two.clone(), empty, one.clone()]);
+    assert_eq!(ids(&["--skip-empty"]), [two, one]);
+    let output = fixture.run(&["--count", "--skip-empty"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\n");
+}
+
+#[test]
+fn headers_list_the_fields_of_every_record() {
+    let fixture = merged("header");
+    let keys = |value: &Value| -> Vec<String> {
+        let mut keys: Vec<_> = value.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    };
+    for args in &[
+        &[][..],
+        &["--with-id", "--with-author"],
+        &[
+            "--with-time",
+            "--with-binary-count",
+            "--verify-net",
+            "--number",
+        ],
+        &["--with-parents", "--with-subject"],
+    ] {
+        let records = fixture.records(&[&["--header"], *args].concat());
+        let schema = records[0]["schema"].as_array().unwrap().iter();
+        let mut schema: Vec<_> = schema.map(|key| key.as_str().unwrap().to_owned()).collect();
+        schema.sort();
+        for record in &records[1..] {
+            assert_eq!(keys(record), schema, "{:?}", args);
+        }
+    }
+    assert_eq!(
+        fixture.run(&["--header", "--format", "csv"]).status.code(),
+        Some(1)
+    );
+    assert_eq!(
+        fixture.run(&["--header", "--per-file"]).status.code(),
+        Some(1)
+    );
+}
+
+#[test]
+fn sorted_output_is_largest_first() {
+    let mut fixture = Fixture::new("sort-output");
+    for (i, lines) in [2, 5, 1, 5, 3].iter().enumerate() {
+        let contents = "x\n".repeat(*lines);
+        fixture.commit(&i.to_string(), &[(&format!("f{}", i), &contents)]);
+    }
+    let sorted = |args: &[&str]| {
+        let args = [&["--with-subject", "--number", "--sort-output"], args].concat();
+        let records = fixture.records(&args);
+        let insertions: Vec<_> = records.iter().map(|r| r["i"].as_u64().unwrap()).collect();
+        let numbers: Vec<_> = records
+            .iter()
+            .map(|r| r["number"].as_u64().unwrap())
+            .collect();
+        (strings(&records, "s"), insertions, numbers)
+    };
+    let (subjects, insertions, numbers) = sorted(&["insertions"]);
+    assert!(insertions.windows(2).all(|pair| pair[0] >= pair[1]));
+    assert_eq!(insertions, [5, 5, 3, 2, 1]);
+    // Ties keep walk order, newest first
+    assert_eq!(subjects, ["3", "1", "4", "0", "2"]);
+    assert_eq!(numbers, [0, 1, 2, 3, 4]);
+    let (subjects, ..) = sorted(&["commit-date", "--reverse"]);
+    assert_eq!(subjects, ["4", "3", "2", "1", "0"]);
+    // Commits are picked from the walk before sorting
+    let (subjects, ..) = sorted(&["insertions", "--max-count", "2"]);
+    assert_eq!(subjects, ["3", "4"]);
+    let output = fixture.run(&["--sort-output", "size"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn date_order_breaks_ties_by_id() {
+    let mut fixture = Fixture::new("date-ties");
+    fixture.commit("base", &[("a", "1\n")]);
+    let branch = fixture.git(&["symbolic-ref", "--short", "HEAD"]);
+    fixture.git(&["checkout", "-q", "-b", "side"]);
+    // Both sides are committed in the same second, and the second side twice
+    fixture.write("b", "1\n");
+    fixture.git(&["add", "-A"]);
+    fixture.git(&["commit", "-q", "-m", "side"]);
+    fixture.git(&["checkout", "-q", branch.trim()]);
+    fixture.write("c", "1\n");
+    fixture.git(&["add", "-A"]);
+    fixture.git(&["commit", "-q", "-m", "main"]);
+    fixture.write("c", "2\n");
+    fixture.git(&["commit", "-q", "-am", "main again"]);
+    fixture.commits += 1;
+    fixture.merge("side");
+    let side = fixture.git(&["rev-parse", "side"]).trim().to_owned();
+    let main = fixture.git(&["rev-parse", "HEAD^1~1"]).trim().to_owned();
+    let again = fixture.git(&["rev-parse", "HEAD^1"]).trim().to_owned();
+    let ids = |args: &[&str]| {
+        let ids = strings(&fixture.records(&[&["--with-id"], args].concat()), "c");
+        ids[1..4].to_vec()
+    };
+    let expected = if side < again {
+        [side, again.clone(), main.clone()]
+    } else if side < main {
+        [again.clone(), side, main.clone()]
+    } else {
+        [again.clone(), main.clone(), side]
+    };
+    assert_eq!(ids(&["--date-order"]), expected);
+    assert_eq!(ids(&["--committer-date-order"]), expected);
+    // Reversed, parents come before their children instead
+    let reversed = ids(&["--date-order", "--reverse"]);
+    assert_eq!(reversed.len(), 3);
+    let position = |id: &String| reversed.iter().position(|r| r == id).unwrap();
+    assert!(position(&main) < position(&again));
+}
+
+#[test]
+fn binary_only_keeps_commits_changing_only_binary_files() {
+    let mut fixture = Fixture::new("binary-only");
+    fixture.commit("source", &[("main.rs", "fn main() {}\n")]);
+    fixture.commit("images", &[("a.png", "\0png\0"), ("b.png", "\0png\0")]);
+    fixture.commit("mixed", &[("c.png", "\0png\0"), ("main.rs", "\n")]);
+    fixture.commit("empty", &[]);
+    let records = fixture.records(&["--binary-only", "--with-subject"]);
+    assert_eq!(strings(&records, "s"), ["images"]);
+    assert!(records[0].get("files").is_none());
+    let records = fixture.records(&["--binary-only", "--with-binary-count"]);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["b"], 2);
+}
+
+#[test]
+fn worktree_counts_uncommitted_changes() {
+    let mut fixture = Fixture::new("worktree");
+    fixture.commit("one", &[("a", "1\n2\n"), ("b", "1\n")]);
+    let stat = |fixture: &Fixture| {
+        let records = fixture.records(&["--worktree"]);
+        assert_eq!(records.len(), 1);
+        let r = &records[0];
+        let counts = ["f", "i", "d"].iter().map(|k| r[k].as_u64().unwrap());
+        counts.collect::<Vec<_>>()
+    };
+    assert_eq!(stat(&fixture), [0, 0, 0]);
+    fixture.write("a", "1\n2\n3\n4\n");
+    assert_eq!(stat(&fixture), [1, 2, 0]);
+    // Staged changes count too, and untracked files don't
+    fixture.write("b", "2\n");
+    fixture.git(&["add", "b"]);
+    fixture.write("c", "new\n");
+    assert_eq!(stat(&fixture), [2, 3, 1]);
+    let records = fixture.records(&["--worktree", "--", "a"]);
+    assert_eq!(records[0]["i"], 2);
+    assert_eq!(fixture.run(&["--worktree", "HEAD"]).status.code(), Some(1));
+    let bare = Fixture::clone(&fixture, "worktree-bare", &["--bare"]);
+    let output = bare.run(&["--worktree"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("bare"));
+}
+
+#[test]
+fn delta_times_are_the_seconds_since_the_first_parent() {
+    let mut fixture = Fixture::new("delta-time");
+    fixture.commit("one", &[("a", "1\n")]);
+    fixture.commits += 9;
+    fixture.commit("two", &[("a", "2\n")]);
+    fixture.commit("three", &[("a", "3\n")]);
+    // A clock gone backwards gives a negative delta
+    fixture.commits -= 5;
+    fixture.commit("four", &[("a", "4\n")]);
+    let records = fixture.records(&["--with-delta-time"]);
+    let deltas: Vec<_> = records.iter().map(|r| r["dt"].as_i64()).collect();
+    assert_eq!(deltas, [Some(-240), Some(60), Some(600), None]);
+    assert!(records[3]["dt"].is_null());
+    let output = fixture.run(&["--with-delta-time", "--format", "csv"]);
+    let csv = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        csv,
+        "files_changed,insertions,deletions,delta_time\n1,1,1,-240\n1,1,1,60\n1,1,1,600\n1,1,0,\n"
+    );
+    assert!(fixture.records(&[]).iter().all(|r| r.get("dt").is_none()));
+}
+
+#[test]
+fn mailmap_merges_an_authors_aliases() {
+    let mut fixture = Fixture::new("use-mailmap");
+    fixture.commit_by("Al <al@home.example>", "one", &[("a", "1\n")]);
+    fixture.commit_by("Alice <alice@work.example>", "two", &[("a", "2\n")]);
+    fixture.commit(
+        "mailmap",
+        &[(
+            ".mailmap",
+            "Alice <alice@example.com> <al@home.example>\n\
+             Alice <alice@example.com> <alice@work.example>\n",
+        )],
+    );
+    let authors = |args: &[&str]| {
+        let records = fixture.records(&[&["--by-author"], args].concat());
+        let counts = records.iter().map(|r| r["n"].as_u64().unwrap());
+        let names = strings(&records, "ae");
+        names.into_iter().zip(counts).collect::<Vec<_>>()
+    };
+    assert_eq!(authors(&[]).len(), 3);
+    assert_eq!(
+        authors(&["--use-mailmap"]),
+        [("alice@example.com".to_owned(), 3)]
+    );
+    let records = fixture.records(&["--use-mailmap", "--with-author"]);
+    assert!(strings(&records, "an").iter().all(|name| name == "Alice"));
+}
+
+#[test]
+fn authors_are_matched_after_the_mailmap() {
+    let mut fixture = Fixture::new("mailmap");
+    fixture.commit(
+        "one",
+        &[(
+            ".mailmap",
+            "Proper <proper@example.com> <alice@example.com>\n",
+        )],
+    );
+    fixture.commit("two", &[("a", "1\n")]);
+    for args in &[
+        &["--author", "Proper"][..],
+        &["--author", "proper@example"],
+        &["--committer", "Proper"],
+    ] {
+        assert_eq!(fixture.records(args).len(), 0, "{:?}", args);
+        let mapped = [&["--use-mailmap"], *args].concat();
+        assert_eq!(fixture.records(&mapped).len(), 2, "{:?}", args);
+    }
+    assert_eq!(
+        fixture
+            .records(&["--use-mailmap", "--author", "Alice"])
+            .len(),
+        0
+    );
+    let regex = [
+        "--use-mailmap",
+        "--author-regex",
+        "^Proper <proper@example\\.com>$",
+    ];
+    assert_eq!(fixture.records(&regex).len(), 2);
+}
+
+#[test]
+fn identity_regexes_match_name_and_email() {
+    let mut fixture = Fixture::new("identity-regex");
+    fixture.commit_by("Alice <alice@example.com>", "one", &[("a", "1\n")]);
+    fixture.commit_by("Alice <alice@example.com.au>", "two", &[("a", "2\n")]);
+    fixture.commit_by("Bob <malice@example.com>", "three", &[("a", "3\n")]);
+    let subjects =
+        |args: &[&str]| strings(&fixture.records(&[&["--with-subject"], args].concat()), "s");
+    assert_eq!(
+        subjects(&["--author-regex", "<alice@example\\.com>$"]),
+        ["one"]
+    );
+    assert_eq!(subjects(&["--author-regex", "^Alice "]), ["two", "one"]);
+    // Substring and regex filters must both match
+    assert_eq!(
+        subjects(&["--author", "example.com.au", "--author-regex", "^Alice "]),
+        ["two"]
+    );
+    // Commits are all by the test's committer
+    assert_eq!(subjects(&["--committer-regex", "^Alice <"]).len(), 3);
+    assert!(subjects(&["--committer-regex", "^Bob"]).is_empty());
+
+    let output = fixture.run(&["--author-regex", "("]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn diff_filters_count_only_some_kinds_of_change() {
+    let mut fixture = Fixture::new("diff-filter");
+    fixture.commit("add", &[("a", "1\n2\n"), ("b", "1\n2\n3\n")]);
+    fixture.git(&["rm", "-q", "b"]);
+    fixture.commit("change", &[("a", "1\n"), ("c", "new\n")]);
+    let stats = |args: &[&str]| -> Vec<Vec<u64>> {
+        let records = fixture.records(&[&["--diff-filter"], args].concat());
+        let stat = |r: &Value| {
+            ["f", "i", "d"]
+                .iter()
+                .map(|k| r[k].as_u64().unwrap())
+                .collect()
+        };
+        records.iter().map(stat).collect()
+    };
+    assert_eq!(stats(&["D"]), [[1, 0, 3], [0, 0, 0]]);
+    assert_eq!(stats(&["M"]), [[1, 0, 1], [0, 0, 0]]);
+    assert_eq!(stats(&["AM"]), [[2, 1, 1], [2, 5, 0]]);
+    assert_eq!(stats(&["d"]), [[2, 1, 1], [2, 5, 0]]);
+    assert_eq!(stats(&["D", "--skip-empty"]), [[1, 0, 3]]);
+    assert_eq!(fixture.run(&["--diff-filter", "X"]).status.code(), Some(1));
+}
+
+#[test]
+fn merge_base_walks_a_branch_since_it_forked() {
+    let mut fixture = Fixture::new("merge-base");
+    fixture.commit("base", &[("a", "1\n")]);
+    let main = fixture.git(&["symbolic-ref", "--short", "HEAD"]);
+    let main = main.trim();
+    fixture.git(&["checkout", "-q", "-b", "feature"]);
+    fixture.commit("feature one", &[("b", "1\n")]);
+    fixture.commit("feature two", &[("b", "2\n")]);
+    fixture.git(&["checkout", "-q", main]);
+    fixture.commit("main", &[("a", "2\n")]);
+    let subjects = |args: &[&str]| {
+        let records = fixture.records(&[&["--with-subject", "--merge-base"], args].concat());
+        strings(&records, "s")
+    };
+    assert_eq!(subjects(&[main, "feature"]), ["feature two", "feature one"]);
+    let fork = fixture.git(&["merge-base", main, "feature"]);
+    let range = format!("{}..feature", fork.trim());
+    let records = fixture.records(&["--with-id", "--merge-base", main, "feature"]);
+    assert_eq!(strings(&records, "c"), fixture.rev_list(&[&range]));
+    // Without a revision, HEAD is walked, here the fork point's own branch
+    assert_eq!(subjects(&["feature"]), ["main"]);
+    let unrelated = fixture.git(&["commit-tree", "-m", "root", "HEAD^{tree}"]);
+    let output = fixture.run(&["--merge-base", unrelated.trim()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no merge base"));
+}
+
+#[test]
+fn validate_reports_what_doesnt_resolve() {
+    let fixture = linear("validate");
+    let output = fixture.run(&["--validate", "HEAD~1", "--", "a"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+    let output = fixture.run(&["--validate", "no-such-ref", "HEAD", "^typo~1"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("no-such-ref"), "{}", stderr);
+    assert!(stderr.contains("^typo~1"), "{}", stderr);
+    let output = fixture.run(&["--validate", "--grep", "("]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn reverts_share_the_tree_from_before() {
+    let mut fixture = Fixture::new("with-tree");
+    fixture.commit("one", &[("a", "1\n")]);
+    fixture.commit("two", &[("a", "2\n")]);
+    fixture.git(&["revert", "--no-edit", "HEAD"]);
+    let records = fixture.records(&["--with-tree"]);
+    let trees = strings(&records, "tree");
+    assert_eq!(trees[0], trees[2]);
+    assert_ne!(trees[0], trees[1]);
+    assert_eq!(trees[0], fixture.git(&["rev-parse", "HEAD^{tree}"]).trim());
+    assert!(fixture.records(&[]).iter().all(|r| r.get("tree").is_none()));
+}
+
+#[test]
+fn tables_are_aligned() {
+    let mut fixture = Fixture::new("table");
+    fixture.commit("one", &[("a", &"x\n".repeat(120))]);
+    fixture.commit("two", &[("a", "x\n"), ("b", "1\n")]);
+    let output = fixture.run(&["--format", "table", "--with-subject", "--with-delta-time"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "files_changed  insertions  deletions  delta_time  subject\n\
+         \x20           2           1        119          60  two\n\
+         \x20           1         120          0              one\n"
+    );
+    // Rows come out uncoloured when not writing to a terminal
+    let output = fixture.run(&["--format", "table"]);
+    assert!(!output.stdout.contains(&b'\x1b'));
+    let output = fixture.run(&["--format", "table", "--per-file"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn max_count_can_be_written_as_git_does() {
+    let mut fixture = Fixture::new("max-count-forms");
+    for i in 0..12 {
+        fixture.commit(&i.to_string(), &[("a", &i.to_string())]);
+    }
+    for args in &[
+        &["-n10"][..],
+        &["-n", "10"],
+        &["--max-count", "10"],
+        &["--max-count=10"],
+    ] {
+        assert_eq!(fixture.records(args).len(), 10, "{:?}", args);
+    }
+    assert_eq!(fixture.records(&["-n3", "--skip", "10"]).len(), 2);
+}
+
+#[test]
+fn cumulative_totals_end_at_the_summary() {
+    let fixture = merged("cumulative");
+    for args in &[&[][..], &["--reverse"]] {
+        let mut records = fixture.records(&[&["--cumulative", "--summary"], *args].concat());
+        let summary = records.pop().unwrap();
+        let (mut i, mut d) = (0, 0);
+        for record in &records {
+            i += record["i"].as_u64().unwrap();
+            d += record["d"].as_u64().unwrap();
+            assert_eq!(record["ci"], i);
+            assert_eq!(record["cd"], d);
+        }
+        let last = records.last().unwrap();
+        assert_eq!(last["ci"], summary["i"]);
+        assert_eq!(last["cd"], summary["d"]);
+    }
+    assert!(fixture.records(&[]).iter().all(|r| r.get("ci").is_none()));
+}
+
+#[test]
+fn objects_count_the_change_in_blob_sizes() {
+    let mut fixture = Fixture::new("objects");
+    fixture.commit("text", &[("a.txt", "hello\n")]);
+    let image = "\0".repeat(1000);
+    fixture.commit("image", &[("a.png", &image)]);
+    fixture.commit("shrink", &[("a.png", &image[..400]), ("a.txt", "hi\n")]);
+    fixture.git(&["rm", "-q", "a.png"]);
+    fixture.commit("delete", &[]);
+    let records = fixture.records(&["--objects"]);
+    let bytes: Vec<_> = records
+        .iter()
+        .map(|r| r["bytes"].as_i64().unwrap())
+        .collect();
+    assert_eq!(bytes, [-400, -603, 1000, 6]);
+    let records = fixture.records(&["--objects", "--", "a.txt"]);
+    let bytes: Vec<_> = records
+        .iter()
+        .map(|r| r["bytes"].as_i64().unwrap())
+        .collect();
+    assert_eq!(bytes, [-3, 6]);
+    let records = fixture.records(&["--objects", "--", ":!a.txt"]);
+    let bytes: Vec<_> = records
+        .iter()
+        .map(|r| r["bytes"].as_i64().unwrap())
+        .collect();
+    assert_eq!(bytes, [-400, -600, 1000]);
+}
+
+#[test]
+fn work_trees_can_live_apart_from_the_git_directory() {
+    let mut source = Fixture::new("work-tree-source");
+    source.commit("one", &[("a", "1\n"), ("src/b", "1\n")]);
+    let bare = Fixture::clone(&source, "work-tree-bare", &["--bare"]);
+    let checkout = Fixture::new("work-tree-checkout");
+    fs::remove_dir_all(checkout.dir.join(".git")).unwrap();
+    let git_dir = bare.dir.to_str().unwrap();
+    let work_tree = checkout.dir.to_str().unwrap();
+    bare.git(&["--work-tree", work_tree, "checkout", "-q", "-f", "HEAD"]);
+    checkout.write("a", "1\n2\n3\n");
+    checkout.write("src/b", "1\n2\n");
+    let args = ["--git-dir", git_dir, "--work-tree", work_tree, "--worktree"];
+    assert_eq!(checkout.records(&args)[0]["i"], 3);
+    // Pathspecs are relative to where in the working tree this is run
+    let output = checkout.run_in("src", &[&args[..], &["--", "b"]].concat());
+    assert_eq!(records(&output)[0]["i"], 1);
+    let output = bare.run(&["--worktree"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--work-tree"));
+    let output = bare.run(&["--work-tree", "no-such-dir", "--worktree"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn keep_going_skips_commits_that_cant_be_diffed() {
+    let fixture = linear("keep-going");
+    // Lose a blob only the second commit has
+    let blob = fixture.git(&["rev-parse", "HEAD~:a"]);
+    let blob = blob.trim();
+    let ids = fixture.rev_list(&["HEAD"]);
+    fs::remove_file(
+        fixture
+            .dir
+            .join(".git/objects")
+            .join(&blob[..2])
+            .join(&blob[2..]),
+    )
+    .unwrap();
+
+    let output = fixture.run(&[]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("commit {}", ids[1])), "{}", stderr);
+
+    for args in [&[][..], &["--sort-output", "commit-date"], &["--jobs", "2"]] {
+        let args = [&["--keep-going", "--with-id"], args].concat();
+        let output = fixture.run(&args);
+        assert_eq!(output.status.code(), Some(1), "{:?}", args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains(&format!("warning: skipping commit {}", ids[1])),
+            "{}",
+            stderr
+        );
+        let ids_written: Vec<_> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<Value>(line).unwrap()["c"]
+                    .as_str()
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect();
+        assert_eq!(ids_written, [ids[0].clone(), ids[2].clone()], "{:?}", args);
+    }
+}
+
+#[test]
+fn limits_truncate_output_with_a_marker() {
+    let mut fixture = Fixture::new("limits");
+    for i in 0..10 {
+        fixture.commit(&i.to_string(), &[("a", &"x\n".repeat(i + 1))]);
+    }
+    let output = fixture.run(&["--limit-bytes", "50"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.last(), Some(&r#"{"truncated":true}"#));
+    let records = &lines[..lines.len() - 1];
+    // Output stops at the first record past the cap
+    let written: usize = records.iter().map(|line| line.len() + 1).sum();
+    assert!(written > 50, "{}", stdout);
+    assert!(
+        written - records.last().unwrap().len() - 1 <= 50,
+        "{}",
+        stdout
+    );
+    assert!(records.len() < 10);
+
+    let output = fixture.run(&["--limit-commits", "4"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 5);
+    assert_eq!(stdout.lines().last(), Some(r#"{"truncated":true}"#));
+
+    // Nothing left out, so no marker
+    let output = fixture.run(&["--limit-commits", "10"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 10);
+
+    for (limit, count) in &[("4", "4\n"), ("20", "10\n")] {
+        let output = fixture.run(&["--count", "--limit-commits", limit]);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), *count);
+    }
+}
+
+#[test]
+fn hunks_are_counted_per_file() {
+    let mut fixture = Fixture::new("hunks");
+    let lines: Vec<_> = (1..=20).map(|i| format!("{}\n", i)).collect();
+    let mut scattered = lines.clone();
+    scattered[1] = "two\n".to_owned();
+    scattered[17] = "eighteen\n".to_owned();
+    let mut contiguous = lines.clone();
+    contiguous[9] = "ten\n".to_owned();
+    contiguous[10] = "eleven\n".to_owned();
+    fixture.commit(
+        "add",
+        &[
+            ("scattered", &lines.concat()),
+            ("contiguous", &lines.concat()),
+        ],
+    );
+    fixture.commit(
+        "edit",
+        &[
+            ("scattered", &scattered.concat()),
+            ("contiguous", &contiguous.concat()),
+        ],
+    );
+    let records = fixture.records(&["--per-file", "--with-hunks", "--max-count", "1"]);
+    let hunks: Vec<_> = records
+        .iter()
+        .map(|r| (r["path"].as_str().unwrap(), r["h"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(hunks, [("contiguous", 1), ("scattered", 2)]);
+    // Enough context joins the two edits into one hunk
+    let records = fixture.records(&["--per-file", "--with-hunks", "--max-count", "1", "-U8"]);
+    assert_eq!(records[1]["h"], 1);
+    let records = fixture.records(&["--per-file", "--max-count", "1"]);
+    assert!(records[0].get("h").is_none());
+    assert_eq!(fixture.run(&["--with-hunks"]).status.code(), Some(1));
+}
+
+#[test]
+fn stable_order_is_independent_of_revision_order() {
+    let mut fixture = Fixture::new("stable");
+    let base = fixture.commit("base", &[("a", "1\n")]);
+    let mut sides = Vec::new();
+    for side in &["one", "two"] {
+        // Both sides are committed at the same two times
+        fixture.git(&["checkout", "-q", "-b", side, &base]);
+        fixture.commits = 1;
+        let first = fixture.commit("first", &[(side, "1\n")]);
+        let second = fixture.commit("second", &[(side, "2\n")]);
+        sides.push((first, second));
+    }
+    let run = |args: &[&str]| fixture.run(&[&["--stable", "--with-id"], args].concat());
+    let output = run(&["one", "two"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, run(&["one", "two"]).stdout);
+    assert_eq!(output.stdout, run(&["two", "one"]).stdout);
+
+    let sorted = |mut ids: Vec<String>| {
+        ids.sort();
+        ids
+    };
+    let mut expected = sorted(vec![sides[0].1.clone(), sides[1].1.clone()]);
+    expected.extend(sorted(vec![sides[0].0.clone(), sides[1].0.clone()]));
+    expected.push(base);
+    assert_eq!(strings(&records(&output), "c"), expected);
+    let reversed = strings(&records(&run(&["--reverse", "two", "one"])), "c");
+    expected.reverse();
+    assert_eq!(reversed, expected);
+}
+
+#[test]
+fn tsv_rows_split_into_one_field_per_column() {
+    let mut fixture = Fixture::new("tsv");
+    fixture.commit("one", &[("a", "1\n")]);
+    fixture.commit_by(
+        "Tab\tbed \\ \"Ng\", Z <zoe@example.com>",
+        "two",
+        &[("a", "2\n3\n")],
+    );
+    let output = fixture.run(&["--format", "tsv", "--with-id", "--with-author"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let rows: Vec<Vec<_>> = stdout
+        .lines()
+        .map(|line| line.split('\t').collect())
+        .collect();
+    assert_eq!(
+        rows[0],
+        [
+            "files_changed",
+            "insertions",
+            "deletions",
+            "id",
+            "author_name",
+            "author_email"
+        ]
+    );
+    assert_eq!(rows.len(), 3);
+    let ids = fixture.rev_list(&["HEAD"]);
+    assert_eq!(
+        rows[1],
+        [
+            "1",
+            "2",
+            "1",
+            &ids[0],
+            "Tab\\tbed \\\\ \"Ng\", Z",
+            "zoe@example.com"
+        ]
+    );
+    assert_eq!(
+        rows[2],
+        ["1", "1", "0", &ids[1], "Alice", "alice@example.com"]
+    );
+}
+
+#[test]
+fn group_by_totals_each_day() {
+    let mut fixture = Fixture::new("group-by");
+    // 2017-07-14 at 02:40 UTC, then a minute later, then a day later
+    fixture.commit("one", &[("a", "1\n")]);
+    fixture.commit("two", &[("a", "1\n2\n"), ("b", "1\n")]);
+    fixture.commits += 24 * 60;
+    fixture.commit("three", &[("b", "")]);
+    let records = fixture.records(&["--group-by", "day"]);
+    assert_eq!(
+        records,
+        [
+            serde_json::json!({ "period": "2017-07-14", "n": 2, "f": 3, "i": 3, "d": 0 }),
+            serde_json::json!({ "period": "2017-07-15", "n": 1, "f": 1, "i": 0, "d": 1 }),
+        ]
+    );
+    let records = fixture.records(&["--group-by", "month"]);
+    assert_eq!(
+        records,
+        [serde_json::json!({ "period": "2017-07", "n": 3, "f": 4, "i": 3, "d": 1 })]
+    );
+    assert_eq!(fixture.run(&["--group-by", "year"]).status.code(), Some(1));
+}
+
+#[test]
+fn base_diffs_every_commit_against_one_tree() {
+    let fixture = linear("base");
+    let stats = |record: &Value| {
+        (
+            record["f"].clone(),
+            record["i"].clone(),
+            record["d"].clone(),
+        )
+    };
+    let default = fixture.records(&["HEAD~1", "--max-count", "1"]);
+    let based = fixture.records(&["HEAD~1", "--max-count", "1", "--base", "HEAD~2"]);
+    assert_eq!(stats(&based[0]), stats(&default[0]));
+
+    // Every commit is measured from the same tree, so later ones include the
+    // earlier ones' changes
+    let based = fixture.records(&["--base", "HEAD~2"]);
+    assert_eq!(based.len(), 3);
+    assert_eq!(stats(&based[0]), (2.into(), 1.into(), 0.into()));
+    assert_eq!(stats(&based[1]), (2.into(), 2.into(), 0.into()));
+    assert_eq!(stats(&based[2]), (0.into(), 0.into(), 0.into()));
+
+    let output = fixture.run(&["--base", "nonexistent"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn submodule_bumps_can_be_left_out_or_kept_alone() {
+    let mut fixture = Fixture::new("submodules");
+    let one = fixture.commit("one", &[("a", "1\n")]);
+    // Gitlinks are staged by hand, as there's no submodule checked out
+    let gitlink = |fixture: &mut Fixture, id: &str, message: &str| {
+        let cacheinfo = format!("160000,{},sub", id);
+        fixture.git(&["update-index", "--add", "--cacheinfo", &cacheinfo]);
+        fixture.git(&["commit", "-q", "-m", message]);
+        fixture.commits += 1;
+        fixture.git(&["rev-parse", "HEAD"]).trim().to_owned()
+    };
+    fixture.write("a", "1\n2\n");
+    fixture.git(&["add", "a"]);
+    let two = gitlink(&mut fixture, &one, "add sub");
+    gitlink(&mut fixture, &two, "bump sub");
+    let stats = |args: &[&str]| {
+        let records = fixture.records(&[&["--with-subject"], args].concat());
+        records
+            .iter()
+            .map(|r| {
+                let s = r["s"].as_str().unwrap().to_owned();
+                (
+                    s,
+                    r["f"].as_u64().unwrap(),
+                    r["i"].as_u64().unwrap(),
+                    r["d"].as_u64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let row = |s: &str, f, i, d| (s.to_owned(), f, i, d);
+    assert_eq!(
+        stats(&[]),
+        [
+            row("bump sub", 1, 1, 1),
+            row("add sub", 2, 2, 0),
+            row("one", 1, 1, 0)
+        ]
+    );
+    assert_eq!(
+        stats(&["--ignore-submodules"]),
+        [
+            row("bump sub", 0, 0, 0),
+            row("add sub", 1, 1, 0),
+            row("one", 1, 1, 0)
+        ]
+    );
+    assert_eq!(
+        stats(&["--submodules-only"]),
+        [row("bump sub", 1, 1, 1), row("add sub", 1, 1, 0)]
+    );
+    let output = fixture.run(&["--ignore-submodules", "--submodules-only"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn errors_can_be_reported_as_json() {
+    let fixture = linear("error-format");
+    let not_a_repo = fixture.dir.join("not-a-repo");
+    fs::create_dir(&not_a_repo).unwrap();
+    let error = |args: &[&str]| {
+        let output = fixture.run(&[&["--error-format", "json"], args].concat());
+        assert_eq!(output.status.code(), Some(1), "{:?}", args);
+        assert!(output.stdout.is_empty());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        serde_json::from_str::<Value>(&stderr).unwrap()
+    };
+    let missing = error(&["--git-dir", not_a_repo.to_str().unwrap()]);
+    assert_eq!(missing["code"], "NotFound");
+    assert_eq!(missing["class"], "Repository");
+    assert!(missing["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("not a git repository"));
+    let unknown = error(&["no-such-revision"]);
+    assert_eq!(unknown["code"], "NotFound");
+    assert_ne!(unknown["class"], "Repository");
+    let generic = error(&["--format", "yaml"]);
+    assert_eq!(generic["code"], "GenericError");
+
+    // The human format is the default
+    let output = fixture.run(&["no-such-revision"]);
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("error: "));
+}
+
+#[test]
+fn only_root_commits_are_flagged_as_roots() {
+    let fixture = merged("root-flag");
+    let records = fixture.records(&["--with-root-flag", "--with-parents"]);
+    assert!(records.len() > 2);
+    let roots: Vec<_> = records.iter().filter(|r| r["root"] == true).collect();
+    assert_eq!(roots.len(), 1);
+    assert_eq!(roots[0]["p"], serde_json::json!([]));
+    assert!(records.iter().all(|r| r["root"].is_boolean()));
+    assert!(fixture.records(&[])[0].get("root").is_none());
+}
+
+#[test]
+fn annotated_tags_are_walked_from_the_commit_they_tag() {
+    let mut fixture = linear("annotated-tags");
+    fixture.git(&["tag", "-a", "-m", "one", "v1", "HEAD~2"]);
+    fixture.git(&["tag", "-a", "-m", "two", "v2", "HEAD~1"]);
+    fixture.commit("four", &[("c", "1\n")]);
+    let ids = |args: &[&str]| strings(&fixture.records(&[&["--with-id"], args].concat()), "c");
+    assert_eq!(ids(&["v2"]), fixture.rev_list(&["v2"]));
+    assert_eq!(ids(&["v1..v2"]), fixture.rev_list(&["v1..v2"]));
+    assert_eq!(
+        ids(&["^v2", "v1", "HEAD"]),
+        fixture.rev_list(&["^v2", "HEAD"])
+    );
+    assert_eq!(ids(&["v2", "--stable"]), fixture.rev_list(&["v2"]));
+    // A tree isn't a commit to walk from
+    let output = fixture.run(&["HEAD^{tree}"]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = fixture.run(&["--validate", "HEAD^{tree}"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(fixture.run(&["--validate", "v1..v2"]).status.success());
+}
+
+#[test]
+fn strict_pathspecs_must_match_some_file() {
+    let fixture = linear("strict-pathspec");
+    let output = fixture.run(&["--", "nope"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = fixture.run(&["--strict-pathspec", "--", "a", "nope"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("pathspec 'nope'"), "{}", stderr);
+
+    let records = fixture.records(&["--strict-pathspec", "--", "a", "b"]);
+    assert_eq!(records.len(), 3);
+    // Only the commits walked count, and `b` came after the first
+    let output = fixture.run(&["--strict-pathspec", "HEAD~2", "--", "b"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn snapshots_count_every_line_of_the_tree() {
+    let mut fixture = linear("snapshot");
+    fixture.commit("four", &[("c", "1\n2\n3\n"), ("bin", "\0\x01\x02")]);
+    let records = fixture.records(&["--snapshot"]);
+    let insertions: Vec<_> = records.iter().map(|r| r["i"].as_u64().unwrap()).collect();
+    assert!(records.iter().all(|r| r["d"] == 0));
+    // Lines in every tracked text file at each commit
+    let lines = |rev: &str| {
+        fixture
+            .git(&["grep", "-I", "-c", "", rev])
+            .lines()
+            .map(|line| line.rsplit(':').next().unwrap().parse::<u64>().unwrap())
+            .sum::<u64>()
+    };
+    let expected: Vec<_> = ["HEAD", "HEAD~1", "HEAD~2", "HEAD~3"]
+        .iter()
+        .map(|rev| lines(rev))
+        .collect();
+    assert_eq!(insertions, expected);
+    assert_eq!(insertions, [5, 2, 3, 1]);
+    assert_eq!(records[0]["f"], 4);
+
+    let records = fixture.records(&["--snapshot", "--skip", "1", "--max-count", "2"]);
+    let insertions: Vec<_> = records.iter().map(|r| r["i"].as_u64().unwrap()).collect();
+    assert_eq!(insertions, [2, 3]);
+    let output = fixture.run(&["--snapshot", "--base", "HEAD~1"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn walk_orders_match_git_on_a_branchy_history() {
+    // The history in the readme, committed a minute apart in order A to G
+    let mut fixture = Fixture::new("walk-orders");
+    let commit = |fixture: &mut Fixture, name: &str| {
+        fixture.commit(name, &[(&name.to_lowercase(), "1\n")]);
+    };
+    commit(&mut fixture, "A");
+    let branch = fixture.git(&["symbolic-ref", "--short", "HEAD"]);
+    fixture.git(&["checkout", "-q", "-b", "side"]);
+    commit(&mut fixture, "B");
+    commit(&mut fixture, "C");
+    fixture.git(&["checkout", "-q", branch.trim()]);
+    commit(&mut fixture, "D");
+    commit(&mut fixture, "E");
+    fixture.git(&["checkout", "-q", "side"]);
+    commit(&mut fixture, "F");
+    fixture.git(&["checkout", "-q", branch.trim()]);
+    fixture.git(&["merge", "-q", "--no-ff", "-m", "M", "side"]);
+    fixture.commits += 1;
+    commit(&mut fixture, "G");
+
+    let subjects = |args: &[&str]| {
+        let records = fixture.records(&[&["--with-subject"], args].concat());
+        strings(&records, "s").concat()
+    };
+    let git = |args: &[&str]| {
+        let log = fixture.git(&[&["log", "--format=%s"], args].concat());
+        log.lines().collect::<String>()
+    };
+    let orders: &[(&[&str], &str)] = &[
+        (&[], "GMFEDCBA"),
+        (&["--topo-order"], "GMFCBEDA"),
+        (&["--date-order"], "GMFEDCBA"),
+        (&["--reverse"], "ABCDEFMG"),
+        (&["--topo-order", "--reverse"], "ADEBCFMG"),
+        (&["--date-order", "--reverse"], "ABCDEFMG"),
+        // Commits are picked before they're reversed
+        (&["--reverse", "-n", "2"], "MG"),
+        (
+            &["--topo-order", "--reverse", "--skip", "1", "-n", "3"],
+            "CFM",
+        ),
+        (&["--date-order", "--reverse", "--skip", "2"], "ABCDEF"),
+    ];
+    for (args, order) in orders {
+        assert_eq!(subjects(args), *order, "{:?}", args);
+        assert_eq!(git(args), *order, "git log {:?}", args);
+    }
+    // Counting picks the same commits
+    let output = fixture.run(&["--count", "--reverse", "-n", "2"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n");
+}
+
+#[test]
+fn ratios_are_fractions_of_the_lines_changed() {
+    let mut fixture = Fixture::new("ratios");
+    fixture.commit("one", &[("a", "1\n2\n")]);
+    fixture.commit("two", &[("a", "1\n3\n4\n5\n")]);
+    fixture.commit("empty", &[]);
+    let records = fixture.records(&["--with-ratios"]);
+    assert!(records[0]["ir"].is_null());
+    assert!(records[0]["dr"].is_null());
+    assert_eq!(
+        (records[1]["i"].clone(), records[1]["d"].clone()),
+        (3.into(), 1.into())
+    );
+    assert_eq!(records[1]["ir"], 0.75);
+    assert_eq!(records[1]["dr"], 0.25);
+    assert_eq!(records[2]["ir"], 1.0);
+    assert_eq!(records[2]["dr"], 0.0);
+    assert!(fixture.records(&[])[1].get("ir").is_none());
+
+    let output = fixture.run(&["--with-ratios", "--format", "csv"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let rows: Vec<_> = stdout.lines().collect();
+    assert_eq!(
+        rows[0],
+        "files_changed,insertions,deletions,insertion_ratio,deletion_ratio"
+    );
+    assert_eq!(rows[1], "0,0,0,,");
+    assert_eq!(rows[2], "1,3,1,0.75,0.25");
+}
+
+#[test]
+fn objects_can_live_in_an_alternate_directory() {
+    let fixture = linear("alternates");
+    let expected = fixture.records(&[]);
+    // Move every object out of the repository
+    let objects = fixture.dir.join(".git/objects");
+    let alternate = fixture.dir.join("alternate");
+    fs::create_dir(&alternate).unwrap();
+    for entry in fs::read_dir(&objects).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_owned();
+        if name != "info" && name != "pack" {
+            fs::rename(&path, alternate.join(name)).unwrap();
+        }
+    }
+    assert_eq!(fixture.run(&[]).status.code(), Some(1));
+
+    let dir = alternate.to_str().unwrap();
+    assert_eq!(fixture.records(&["--object-dir", dir]), expected);
+    assert_eq!(
+        fixture.records(&["--object-dir", dir, "--jobs", "2"]),
+        expected
+    );
+    assert_eq!(
+        fixture.run(&["--object-dir", "nope"]).status.code(),
+        Some(1)
+    );
+
+    // The repository's own alternates are read without asking
+    fs::write(objects.join("info/alternates"), format!("{}\n", dir)).unwrap();
+    assert_eq!(fixture.records(&[]), expected);
+}
+
+#[test]
+fn tips_only_emits_each_revision_in_the_order_given() {
+    let fixture = merged("tips-only");
+    let revs = ["side", "HEAD", "HEAD~2"];
+    let records = fixture.records(&[&["--tips-only", "--with-id"], &revs[..]].concat());
+    let expected: Vec<_> = revs
+        .iter()
+        .flat_map(|rev| fixture.rev_list(&["-n", "1", rev]))
+        .collect();
+    assert_eq!(strings(&records, "c"), expected);
+    assert_eq!(fixture.records(&["--tips-only"]).len(), 1);
+    assert_eq!(
+        fixture
+            .records(&["--tips-only", "--min-insertions", "2"])
+            .len(),
+        0
+    );
+    for args in &[
+        &["HEAD~2..HEAD"][..],
+        &["^side", "HEAD"],
+        &["--author", "Alice"],
+        &["--since", "1970-01-01"],
+        &["--no-merges"],
+        &["--changed-path", "a"],
+        &["--sample", "0.5"],
+        &["--", "a"],
+    ] {
+        let output = fixture.run(&[&["--tips-only"], *args].concat());
+        assert!(!output.status.success(), "{:?}", args);
+    }
+}
+
+#[test]
+fn dirs_counts_distinct_directories_changed() {
+    let mut fixture = Fixture::new("with-dirs");
+    fixture.commit("one dir", &[("a/x.rs", "1\n"), ("a/y.rs", "1\n")]);
+    fixture.commit("two dirs", &[("a/x.rs", "2\n"), ("b/y.rs", "1\n")]);
+    fixture.commit("top", &[("c", "1\n"), ("d", "1\n")]);
+    let records = fixture.records(&["--with-dirs"]);
+    let dirs: Vec<_> = records
+        .iter()
+        .map(|record| record["dirs"].clone())
+        .collect();
+    assert_eq!(
+        dirs,
+        [
+            serde_json::json!(1),
+            serde_json::json!(2),
+            serde_json::json!(1)
+        ]
+    );
+    assert!(fixture.records(&[])[0].get("dirs").is_none());
+}
+
+#[test]
+fn generated_files_are_left_out_of_the_stats() {
+    let mut fixture = Fixture::new("ignore-generated");
+    fixture.commit(
+        "source and lockfile",
+        &[("src/main.rs", "1\n2\n"), ("Cargo.lock", "1\n2\n3\n")],
+    );
+    fixture.commit("lockfile", &[("Cargo.lock", "1\n")]);
+    fixture.commit("generated", &[("api/x.pb.go", "1\n"), ("x.pb.go", "1\n")]);
+    let counts = |args: &[&str]| {
+        fixture
+            .records(args)
+            .iter()
+            .map(|record| (record["i"].clone(), record["d"].clone()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        counts(&["--ignore-generated", "Cargo.lock"]),
+        [
+            (serde_json::json!(2), serde_json::json!(0)),
+            (serde_json::json!(0), serde_json::json!(0)),
+            (serde_json::json!(2), serde_json::json!(0)),
+        ]
+    );
+    assert_eq!(
+        counts(&[
+            "--ignore-generated",
+            "Cargo.lock",
+            "--ignore-generated",
+            "*.pb.go",
+        ])
+        .first(),
+        Some(&(serde_json::json!(0), serde_json::json!(0)))
+    );
+    assert_eq!(counts(&["--", ":!Cargo.lock"]).len(), 2);
+}
+
+#[test]
+fn window_averages_the_last_n_records() {
+    let mut fixture = Fixture::new("window");
+    let mut lines = String::new();
+    for n in &[3, 6, 0, 9] {
+        for _ in 0..*n {
+            lines.push_str("x\n");
+        }
+        fixture.commit("grow", &[("a", &lines)]);
+    }
+    let averages = |args: &[&str]| {
+        let records = fixture.records(&[&["--window", "3"], args].concat());
+        records
+            .iter()
+            .map(|record| (record["ima"].as_f64(), record["dma"].as_f64()))
+            .collect::<Vec<_>>()
+    };
+    // Insertions of 3, 6, 0 and 9 in commit order
+    assert_eq!(
+        averages(&["--reverse"]),
+        [
+            (Some(3.0), Some(0.0)),
+            (Some(4.5), Some(0.0)),
+            (Some(3.0), Some(0.0)),
+            (Some(5.0), Some(0.0)),
+        ]
+    );
+    assert_eq!(
+        averages(&[]),
+        [
+            (Some(9.0), Some(0.0)),
+            (Some(4.5), Some(0.0)),
+            (Some(5.0), Some(0.0)),
+            (Some(3.0), Some(0.0)),
+        ]
+    );
+    assert!(!fixture.run(&["--window", "0"]).status.success());
+}
+
+#[test]
+fn changed_path_keeps_commits_changing_the_tree_at_a_path() {
+    let mut fixture = Fixture::new("changed-path");
+    fixture.commit("source", &[("src/main.rs", "1\n")]);
+    fixture.commit("docs", &[("docs/guide.md", "1\n")]);
+    fixture.commit("both", &[("src/main.rs", "2\n"), ("docs/guide.md", "2\n")]);
+    fixture.git(&["checkout", "-q", "-b", "side", "HEAD~1"]);
+    fixture.commit("side docs", &[("docs/side.md", "1\n")]);
+    fixture.git(&["checkout", "-q", "-"]);
+    fixture.merge("side");
+    let subjects =
+        |args: &[&str]| strings(&fixture.records(&[&["--with-subject"], args].concat()), "s");
+    // The merge's `src/` is its first parent's, so it's left out
+    assert_eq!(subjects(&["--changed-path", "src"]), ["both", "source"]);
+    assert_eq!(subjects(&["--changed-path", "src/"]), ["both", "source"]);
+    assert_eq!(
+        subjects(&["--changed-path", "src/main.rs", "--first-parent"]),
+        ["both", "source"]
+    );
+    assert_eq!(subjects(&["--changed-path", "nope"]), Vec::<String>::new());
+    // Stats are still those of the whole commit
+    let records = fixture.records(&["--changed-path", "src", "-n", "1"]);
+    assert_eq!(records[0]["f"], serde_json::json!(2));
+}
+
+#[test]
+fn ranges_select_the_commits_rev_list_does() {
+    let mut fixture = merged("ranges");
+    fixture.git(&["checkout", "-q", "side"]);
+    fixture.commit("side again", &[("b", "1\n")]);
+    fixture.git(&["checkout", "-q", "-"]);
+    fixture.git(&["tag", "-a", "-m", "annotated", "annotated", "side"]);
+    fixture.git(&["tag", "lightweight", "HEAD~1"]);
+    let sorted = |mut ids: Vec<String>| {
+        ids.sort();
+        ids
+    };
+    for args in &[
+        &["HEAD"][..],
+        &["side..HEAD"],
+        &["HEAD..side"],
+        &["side...HEAD"],
+        &["HEAD...side"],
+        &["^side", "HEAD"],
+        &["HEAD", "^side"],
+        &["^HEAD~1", "side", "HEAD"],
+        &["side.."],
+        &["annotated"],
+        &["lightweight"],
+        &["annotated..lightweight"],
+        &["lightweight...annotated"],
+        &["^annotated", "HEAD"],
+    ] {
+        let records = fixture.records(&[&["--with-id"], *args].concat());
+        // rev-list lists each commit once, so this catches any emitted twice
+        assert_eq!(
+            sorted(strings(&records, "c")),
+            sorted(fixture.rev_list(args)),
+            "{:?}",
+            args
+        );
+    }
+}
+
+#[test]
+fn messages_and_authors_are_decoded_from_their_encoding() {
+    let mut fixture = Fixture::new("encoding");
+    fixture.commit("one", &[("a", "1\n")]);
+    // git converts what it's given, so write the Latin-1 commit by hand
+    let tree = fixture.git(&["write-tree"]);
+    let parent = fixture.git(&["rev-parse", "HEAD"]);
+    let mut raw = format!("tree {}parent {}", tree, parent).into_bytes();
+    raw.extend_from_slice(b"author Zo\xeb Ng <zoe@example.com> 1500000060 +0000\n");
+    raw.extend_from_slice(b"committer Zo\xeb Ng <zoe@example.com> 1500000060 +0000\n");
+    raw.extend_from_slice(b"encoding ISO-8859-1\n\nCaf\xe9 \x80\n");
+    fs::write(fixture.dir.join("raw"), raw).unwrap();
+    let id = fixture.git(&["hash-object", "-t", "commit", "-w", "raw"]);
+    fixture.git(&["update-ref", "HEAD", id.trim()]);
+    let args = ["--with-subject", "--with-author", "-n", "1"];
+    let record = &fixture.records(&args)[0];
+    assert_eq!(record["s"], "Café \u{80}");
+    assert_eq!(record["an"], "Zoë Ng");
+    let record = &fixture.records(&[&args[..], &["--encoding", "windows-1252"]].concat())[0];
+    assert_eq!(record["s"], "Café €");
+    let record = &fixture.records(&[&args[..], &["--encoding", "utf-8"]].concat())[0];
+    assert_eq!(record["s"], "Caf\u{fffd} \u{fffd}");
+    assert_eq!(fixture.records(&["--author", "zoë"]).len(), 1);
+    assert_eq!(fixture.records(&["--grep", "^Café"]).len(), 1);
+    assert!(!fixture.run(&["--encoding", "ebcdic"]).status.success());
+}
+
+#[test]
+fn samples_are_picked_the_same_every_run() {
+    let mut fixture = Fixture::new("sample");
+    for n in 0..20 {
+        fixture.commit(&n.to_string(), &[("a", &n.to_string())]);
+    }
+    let ids = |rate: &str| strings(&fixture.records(&["--with-id", "--sample", rate]), "c");
+    assert_eq!(ids("1.0"), fixture.rev_list(&["HEAD"]));
+    assert!(ids("0.0").is_empty());
+    let half = ids("0.5");
+    assert!(!half.is_empty() && half.len() < 20, "{}", half.len());
+    assert_eq!(ids("0.5"), half);
+    // A smaller sample is a subset of a larger one
+    assert!(ids("0.25").iter().all(|id| half.contains(id)));
+    for rate in &["-0.1", "1.5", "half"] {
+        assert!(!fixture.run(&["--sample", rate]).status.success());
+    }
+}
+
+#[test]
+fn churn_is_insertions_plus_deletions() {
+    let mut fixture = linear("churn");
+    fixture.commit("delete", &[("a", "")]);
+    let records = fixture.records(&["--with-churn"]);
+    assert_eq!(records.len(), 4);
+    for record in &records {
+        let sum = record["i"].as_u64().unwrap() + record["d"].as_u64().unwrap();
+        assert_eq!(record["churn"], sum);
+    }
+    // The deletion-only commit
+    assert_eq!(records[0]["i"], 0);
+    assert_eq!(records[0]["churn"], 2);
+    assert_eq!(fixture.records(&["--min-churn", "2"]).len(), 2);
+    assert_eq!(fixture.records(&["--max-churn", "1"]).len(), 2);
+    let records = fixture.records(&["--with-churn", "--sort-output", "churn"]);
+    let churn: Vec<_> = records
+        .iter()
+        .map(|record| record["churn"].clone())
+        .collect();
+    assert_eq!(churn, [2, 2, 1, 1]);
+}
+
+#[test]
+fn globs_walk_from_every_matching_ref() {
+    let mut fixture = merged("glob");
+    fixture.git(&["tag", "v1", "HEAD~2"]);
+    fixture.git(&["tag", "-a", "-m", "side", "v2", "side"]);
+    fixture.git(&["checkout", "-q", "-b", "loose", "HEAD~3"]);
+    fixture.commit("loose", &[("d", "1\n")]);
+    fixture.git(&["tag", "v3"]);
+    fixture.git(&["checkout", "-q", "-"]);
+    fixture.git(&["tag", "tree", "HEAD^{tree}"]);
+    let sorted = |mut ids: Vec<String>| {
+        ids.sort();
+        ids
+    };
+    let ids = |args: &[&str]| {
+        sorted(strings(
+            &fixture.records(&[&["--with-id"], args].concat()),
+            "c",
+        ))
+    };
+    let expected = sorted(fixture.rev_list(&["v1", "v2", "v3"]));
+    assert_eq!(ids(&["--glob", "refs/tags/*"]), expected);
+    assert_eq!(ids(&["--glob", "tags"]), expected);
+    assert_eq!(ids(&["--tags"]), expected);
+    assert_eq!(
+        ids(&["--branches"]),
+        sorted(fixture.rev_list(&["--branches"]))
+    );
+    assert_eq!(
+        ids(&["--tags", "^side"]),
+        sorted(fixture.rev_list(&["--tags", "^side"]))
+    );
+    assert!(ids(&["--glob", "refs/nope/*"]).is_empty());
+    assert_eq!(fixture.records(&["--tips-only", "--tags"]).len(), 3);
+}
+
+#[test]
+fn all_walks_every_ref_once() {
+    let mut fixture = merged("all");
+    fixture.git(&["checkout", "-q", "-b", "other", "HEAD~3"]);
+    let other = fixture.commit("other", &[("d", "1\n")]);
+    fixture.git(&["checkout", "-q", "--detach", "HEAD~1"]);
+    let detached = fixture.commit("detached", &[("e", "1\n")]);
+    fixture.git(&["tag", "-a", "-m", "tag", "v1", "side"]);
+    let ids = strings(&fixture.records(&["--all", "--with-id"]), "c");
+    let mut expected = fixture.rev_list(&["--all"]);
+    assert_eq!(ids.len(), expected.len());
+    assert!(ids.contains(&other) && ids.contains(&detached));
+    let mut sorted = ids.clone();
+    sorted.sort();
+    sorted.dedup();
+    expected.sort();
+    assert_eq!(sorted, expected);
+    assert_eq!(
+        fixture.records(&["--all", "--no-merges"]).len(),
+        expected.len() - 1
+    );
+    assert_eq!(fixture.records(&["--all", "--", "d"]).len(), 1);
+}
+
+#[test]
+fn broken_pipes_end_the_run_quietly() {
+    let mut fixture = Fixture::new("broken-pipe");
+    for n in 0..50 {
+        fixture.commit(&n.to_string(), &[("a", &"x\n".repeat(n + 1))]);
+    }
+    for args in &[
+        &[][..],
+        &["--format", "csv"],
+        &["--format", "json-array"],
+        &["--count"],
+    ] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_shortstat-dump"))
+            .args(*args)
+            .current_dir(&fixture.dir)
+            .env("HOME", &fixture.dir)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // Close the reading end before anything is written
+        drop(child.stdout.take());
+        let output = child.wait_with_output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{:?}: {}", args, stderr);
+        assert!(stderr.is_empty(), "{:?}: {}", args, stderr);
+    }
+}
+
+#[test]
+fn ancestry_paths_keep_only_commits_between_a_ranges_ends() {
+    // An old branch forking before A, then a diamond from A to its merge
+    let mut fixture = Fixture::new("ancestry-path");
+    fixture.commit("root", &[("a", "1\n")]);
+    fixture.git(&["checkout", "-q", "-b", "old"]);
+    fixture.commit("old", &[("o", "1\n")]);
+    fixture.git(&["checkout", "-q", "-"]);
+    let a = fixture.commit("A", &[("a", "2\n")]);
+    fixture.git(&["checkout", "-q", "-b", "left"]);
+    fixture.commit("left", &[("l", "1\n")]);
+    fixture.git(&["checkout", "-q", "-"]);
+    fixture.commit("right", &[("r", "1\n")]);
+    fixture.merge("left");
+    fixture.merge("old");
+    fixture.commit("after", &[("c", "1\n")]);
+    let range = format!("{}..HEAD", a);
+    let subjects = |args: &[&str]| {
+        let args = [&["--with-subject", "--ancestry-path"], args].concat();
+        let mut subjects = strings(&fixture.records(&args), "s");
+        subjects.sort();
+        subjects
+    };
+    assert_eq!(
+        subjects(&[&range]),
+        ["after", "left", "merge", "merge", "right"]
+    );
+    let ids = strings(
+        &fixture.records(&["--with-id", "--ancestry-path", &range]),
+        "c",
+    );
+    assert_eq!(ids, fixture.rev_list(&["--ancestry-path", &range]));
+    // Without it, the old branch is in the range too
+    assert_eq!(fixture.records(&[&range]).len(), 6);
+    assert_eq!(subjects(&["^old", "left"]), Vec::<String>::new());
+    let output = fixture.run(&["--ancestry-path", "HEAD"]);
+    assert!(!output.status.success());
+}